@@ -0,0 +1,35 @@
+// Lists the migrations in migrations/ for `youkebox migrate status` and /ready,
+// their SQL is compiled in by diesel's embed_migrations!. Both have to be rebuilt
+// when a migration is added, which cargo only does when it's told to.
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+
+    let mut migrations: Vec<String> = fs::read_dir("migrations")
+        .expect("Unable to read migrations/")
+        .map(|entry| entry.expect("Unable to read migrations/"))
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    migrations.sort();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs");
+    let mut file = File::create(out).expect("Unable to write the migration list");
+
+    writeln!(file, "pub static MIGRATIONS: &[MigrationInfo] = &[").unwrap();
+
+    for migration in migrations {
+        let mut parts = migration.splitn(2, '_');
+        let version = parts.next().unwrap_or_default();
+        let name = parts.next().unwrap_or_default();
+
+        writeln!(file, "    MigrationInfo {{ version: {:?}, name: {:?} }},", version, name).unwrap();
+    }
+
+    writeln!(file, "];").unwrap();
+}
@@ -0,0 +1,9 @@
+CREATE TABLE room_settings (
+    "room_id"               BIGINT      PRIMARY KEY REFERENCES rooms (id) ON DELETE CASCADE,
+    "max_song_length"       INTEGER     DEFAULT NULL,
+    "max_queue_per_user"    INTEGER     DEFAULT NULL,
+    "skip_policy"           VARCHAR     NOT NULL DEFAULT 'anyone',
+    "duplicate_policy"      VARCHAR     NOT NULL DEFAULT 'allow',
+    "fallback_mode"         VARCHAR     NOT NULL DEFAULT 'silence',
+    "allowed_sources"       VARCHAR[]   NOT NULL DEFAULT '{youtube}'
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE sessions;
+DROP TABLE room_members;
+ALTER TABLE rooms DROP COLUMN invite_token;
+ALTER TABLE rooms DROP COLUMN owner_id;
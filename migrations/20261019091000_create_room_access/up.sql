@@ -0,0 +1,16 @@
+ALTER TABLE rooms ADD COLUMN "owner_id" BIGINT DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL;
+ALTER TABLE rooms ADD COLUMN "invite_token" VARCHAR NOT NULL DEFAULT md5(random()::text || clock_timestamp()::text);
+
+CREATE TABLE room_members (
+    "room_id"       BIGINT      NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    "user_id"       BIGINT      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    "joined_on"     TIMESTAMP   NOT NULL DEFAULT now(),
+    PRIMARY KEY (room_id, user_id)
+);
+
+CREATE TABLE sessions (
+    "token"         VARCHAR     PRIMARY KEY,
+    "user_id"       BIGINT      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    "created_on"    TIMESTAMP   NOT NULL DEFAULT now(),
+    "expires_on"    TIMESTAMP   NOT NULL
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE room_access_tokens;
+ALTER TABLE rooms DROP COLUMN password;
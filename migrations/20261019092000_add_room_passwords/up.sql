@@ -0,0 +1,8 @@
+ALTER TABLE rooms ADD COLUMN "password" VARCHAR DEFAULT NULL;
+
+CREATE TABLE room_access_tokens (
+    "token"         VARCHAR     PRIMARY KEY,
+    "room_id"       BIGINT      NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    "created_on"    TIMESTAMP   NOT NULL DEFAULT now(),
+    "expires_on"    TIMESTAMP   NOT NULL
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX last_activity_at_on_rooms;
+ALTER TABLE rooms DROP COLUMN archived_at;
+ALTER TABLE rooms DROP COLUMN last_activity_at;
+ALTER TABLE rooms DROP COLUMN created_at;
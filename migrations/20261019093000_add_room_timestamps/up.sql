@@ -0,0 +1,15 @@
+ALTER TABLE rooms ADD COLUMN "created_at" TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE rooms ADD COLUMN "last_activity_at" TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE rooms ADD COLUMN "archived_at" TIMESTAMP DEFAULT NULL;
+
+-- Existing rooms were last active when their latest song was added or started
+UPDATE rooms SET last_activity_at = COALESCE(
+    (
+        SELECT GREATEST(MAX(videos.added_on), MAX(videos.started_on))
+        FROM videos
+        WHERE videos.room_id = rooms.id
+    ),
+    now()
+);
+
+CREATE INDEX last_activity_at_on_rooms ON rooms (last_activity_at);
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN is_admin;
+
+DELETE FROM rooms WHERE deleted_at IS NOT NULL;
+DROP INDEX unique_name_on_rooms;
+CREATE UNIQUE INDEX unique_name_on_rooms ON rooms (lower(name));
+ALTER TABLE rooms ADD CONSTRAINT rooms_name_key UNIQUE (name);
+
+ALTER TABLE rooms DROP COLUMN deleted_at;
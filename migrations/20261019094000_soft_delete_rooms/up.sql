@@ -0,0 +1,8 @@
+ALTER TABLE rooms ADD COLUMN "deleted_at" TIMESTAMP DEFAULT NULL;
+
+-- Deleted rooms shouldn't keep their name taken
+ALTER TABLE rooms DROP CONSTRAINT rooms_name_key;
+DROP INDEX unique_name_on_rooms;
+CREATE UNIQUE INDEX unique_name_on_rooms ON rooms (lower(name)) WHERE deleted_at IS NULL;
+
+ALTER TABLE users ADD COLUMN "is_admin" BOOLEAN NOT NULL DEFAULT 'f';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE room_slugs;
+DROP INDEX unique_slug_on_rooms;
+ALTER TABLE rooms DROP COLUMN "slug";
@@ -0,0 +1,25 @@
+ALTER TABLE rooms ADD COLUMN "slug" VARCHAR;
+
+UPDATE rooms SET slug = COALESCE(
+    NULLIF(trim(both '-' from regexp_replace(lower(name), '[^[:alnum:]]+', '-', 'g')), ''),
+    'room'
+);
+
+-- Names that only differ in punctuation end up with the same slug
+UPDATE rooms SET slug = rooms.slug || '-' || rooms.id
+FROM (
+    SELECT id, row_number() OVER (PARTITION BY slug ORDER BY id) AS n FROM rooms
+) AS duplicates
+WHERE rooms.id = duplicates.id AND duplicates.n > 1;
+
+ALTER TABLE rooms ALTER COLUMN "slug" SET NOT NULL;
+CREATE UNIQUE INDEX unique_slug_on_rooms ON rooms (slug) WHERE deleted_at IS NULL;
+
+-- Every slug a room ever had, so links keep working after a rename
+CREATE TABLE room_slugs (
+    slug VARCHAR PRIMARY KEY,
+    room_id BIGINT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+INSERT INTO room_slugs (slug, room_id) SELECT slug, id FROM rooms;
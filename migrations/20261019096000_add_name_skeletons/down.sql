@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX unique_username_skeleton_on_users;
+ALTER TABLE users DROP COLUMN "username_skeleton";
+
+DROP INDEX unique_name_skeleton_on_rooms;
+ALTER TABLE rooms DROP COLUMN "name_skeleton";
@@ -0,0 +1,7 @@
+-- Lowercased UTS #39 skeletons, names that look alike share the same one.
+-- They are filled in by the application, so they start out empty.
+ALTER TABLE rooms ADD COLUMN "name_skeleton" VARCHAR DEFAULT NULL;
+CREATE UNIQUE INDEX unique_name_skeleton_on_rooms ON rooms (name_skeleton) WHERE deleted_at IS NULL;
+
+ALTER TABLE users ADD COLUMN "username_skeleton" VARCHAR DEFAULT NULL;
+CREATE UNIQUE INDEX unique_username_skeleton_on_users ON users (username_skeleton);
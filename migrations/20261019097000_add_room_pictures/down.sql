@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE rooms DROP COLUMN "picture_type";
+ALTER TABLE rooms DROP COLUMN "picture_path";
@@ -0,0 +1,3 @@
+-- Relative to the pictures directory, eg: 42.png
+ALTER TABLE rooms ADD COLUMN "picture_path" VARCHAR DEFAULT NULL;
+ALTER TABLE rooms ADD COLUMN "picture_type" VARCHAR DEFAULT NULL;
@@ -0,0 +1,6 @@
+-- Only used when PICTURE_STORAGE is set to "database"
+CREATE TABLE pictures (
+    key VARCHAR PRIMARY KEY,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
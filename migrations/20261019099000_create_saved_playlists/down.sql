@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE saved_playlist_videos;
+DROP TABLE saved_playlists;
@@ -0,0 +1,20 @@
+CREATE TABLE saved_playlists (
+    "id"            BIGSERIAL   PRIMARY KEY,
+    "user_id"       BIGINT      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    "name"          VARCHAR     NOT NULL,
+    "created_at"    TIMESTAMP   NOT NULL DEFAULT now(),
+    "updated_at"    TIMESTAMP   NOT NULL DEFAULT now(),
+    UNIQUE (user_id, name)
+);
+
+-- The metadata is cached so a playlist can be queued without asking YouTube again
+CREATE TABLE saved_playlist_videos (
+    "id"            BIGSERIAL   PRIMARY KEY,
+    "playlist_id"   BIGINT      NOT NULL REFERENCES saved_playlists (id) ON DELETE CASCADE,
+    "video_id"      VARCHAR     NOT NULL,
+    "title"         VARCHAR     NOT NULL,
+    "description"   VARCHAR,
+    "duration"      VARCHAR     NOT NULL
+);
+
+CREATE INDEX saved_playlist_videos_playlist_id ON saved_playlist_videos (playlist_id);
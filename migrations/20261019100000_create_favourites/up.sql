@@ -0,0 +1,11 @@
+-- The metadata is kept, favourites outlive the history of the room they were played in
+CREATE TABLE favourites (
+    "user_id"       BIGINT      NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    "video_id"      VARCHAR     NOT NULL,
+    "title"         VARCHAR     NOT NULL,
+    "description"   VARCHAR,
+    "duration"      VARCHAR     NOT NULL,
+    "room_id"       BIGINT      REFERENCES rooms (id) ON DELETE SET NULL,
+    "added_on"      TIMESTAMP   NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, video_id)
+);
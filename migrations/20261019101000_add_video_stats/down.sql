@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX videos_room_id_started_on;
+
+ALTER TABLE saved_playlist_videos DROP COLUMN channel;
+
+ALTER TABLE videos DROP COLUMN skipped_on;
+ALTER TABLE videos DROP COLUMN added_by;
+ALTER TABLE videos DROP COLUMN channel;
@@ -0,0 +1,8 @@
+-- Who queued a song, which channel it comes from and when it was skipped, for the room statistics
+ALTER TABLE videos ADD COLUMN "channel" VARCHAR DEFAULT NULL;
+ALTER TABLE videos ADD COLUMN "added_by" BIGINT DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL;
+ALTER TABLE videos ADD COLUMN "skipped_on" TIMESTAMP DEFAULT NULL;
+
+ALTER TABLE saved_playlist_videos ADD COLUMN "channel" VARCHAR DEFAULT NULL;
+
+CREATE INDEX videos_room_id_started_on ON videos (room_id, started_on);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX videos_added_on;
+DROP INDEX favourites_added_on;
+DROP TABLE trending_videos;
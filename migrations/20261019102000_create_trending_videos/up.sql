@@ -0,0 +1,13 @@
+-- Rebuilt by the trending job, so the lobby doesn't have to aggregate the videos of every room
+CREATE TABLE trending_videos (
+    "video_id"      VARCHAR     PRIMARY KEY,
+    "title"         VARCHAR     NOT NULL,
+    "channel"       VARCHAR,
+    "queued"        BIGINT      NOT NULL,
+    "likes"         BIGINT      NOT NULL,
+    "rooms"         BIGINT      NOT NULL,
+    "computed_at"   TIMESTAMP   NOT NULL
+);
+
+CREATE INDEX favourites_added_on ON favourites (added_on);
+CREATE INDEX videos_added_on ON videos (added_on);
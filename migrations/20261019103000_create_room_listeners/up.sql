@@ -0,0 +1,11 @@
+-- Clients send a heartbeat while they're in a room, listeners that stop sending one are gone
+CREATE TABLE room_listeners (
+    "room_id"       BIGINT      NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    "listener_id"   VARCHAR     NOT NULL,
+    "user_id"       BIGINT      REFERENCES users (id) ON DELETE CASCADE,
+    "joined_on"     TIMESTAMP   NOT NULL,
+    "last_seen"     TIMESTAMP   NOT NULL,
+    PRIMARY KEY (room_id, listener_id)
+);
+
+CREATE INDEX room_listeners_last_seen ON room_listeners (last_seen);
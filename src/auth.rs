@@ -0,0 +1,268 @@
+use diesel;
+use diesel::pg::PgConnection;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use rocket::http::Status;
+use rocket::request::{self, FormItems, FromRequest};
+use rocket::response::Failure;
+use rocket::{Outcome, Request};
+use std::time::{Duration, SystemTime};
+
+use schema::{room_access_tokens, sessions};
+use user::User;
+use DbConn;
+
+// Sessions stay valid for 30 days
+const SESSION_LIFETIME: u64 = 30 * 24 * 60 * 60;
+// Room access tokens stay valid for 12 hours
+const ROOM_ACCESS_LIFETIME: u64 = 12 * 60 * 60;
+
+#[derive(Serialize, Queryable)]
+pub struct Session {
+    pub token: String,
+    pub user_id: i64,
+    pub created_on: SystemTime,
+    pub expires_on: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession {
+    pub token: String,
+    pub user_id: i64,
+    pub expires_on: SystemTime,
+}
+
+/// Handed out in exchange for a room's join password
+#[derive(Serialize, Queryable)]
+pub struct RoomAccessToken {
+    pub token: String,
+    pub room_id: i64,
+    pub created_on: SystemTime,
+    pub expires_on: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "room_access_tokens"]
+pub struct NewRoomAccessToken {
+    pub token: String,
+    pub room_id: i64,
+    pub expires_on: SystemTime,
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+/// The raw token from an `Authorization: Bearer <token>` header
+pub struct BearerToken(pub String);
+
+/// A user that sent a valid session token
+pub struct AuthUser(pub User);
+
+/// A logged in user with the admin flag set
+pub struct AdminUser(pub User);
+
+/// Whatever a client can show to get into a room:
+/// a logged in user, a room invite token and/or a room access token.
+pub struct RoomCredentials {
+    pub user: Option<User>,
+    pub invite_token: Option<String>,
+    pub access_token: Option<String>,
+}
+
+/// Returns a random alphanumeric string, used for session and invite tokens
+pub fn generate_token(length: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .collect()
+}
+
+impl Session {
+    #[inline]
+    pub fn create(conn: &PgConnection, user: &User) -> Result<Session, Failure> {
+        use diesel::prelude::*;
+
+        let new_session = NewSession {
+            token: generate_token(48),
+            user_id: user.id,
+            expires_on: SystemTime::now() + Duration::from_secs(SESSION_LIFETIME),
+        };
+
+        let result = diesel::insert_into(sessions::table)
+            .values(&new_session)
+            .get_result(conn);
+
+        match result {
+            Ok(session) => Ok(session),
+            Err(e) => {
+                error!("Error while creating a session: {}", e);
+                Err(Failure(Status::InternalServerError))
+            }
+        }
+    }
+
+    // Find the user behind a session token that hasn't expired yet
+    #[inline]
+    pub fn user(conn: &PgConnection, session_token: &str) -> Option<User> {
+        use diesel::prelude::*;
+        use schema::sessions::dsl::*;
+        use schema::users;
+
+        users::table
+            .inner_join(sessions)
+            .filter(token.eq(session_token))
+            .filter(expires_on.gt(SystemTime::now()))
+            .select(users::all_columns)
+            .first::<User>(conn)
+            .ok()
+    }
+
+    #[inline]
+    pub fn delete(conn: &PgConnection, session_token: &str) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::sessions::dsl::*;
+
+        let result = diesel::delete(sessions.filter(token.eq(session_token))).execute(conn);
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Failure(Status::InternalServerError)),
+        }
+    }
+}
+
+impl RoomAccessToken {
+    #[inline]
+    pub fn create(conn: &PgConnection, r_id: i64) -> Result<RoomAccessToken, Failure> {
+        use diesel::prelude::*;
+
+        let new_token = NewRoomAccessToken {
+            token: generate_token(48),
+            room_id: r_id,
+            expires_on: SystemTime::now() + Duration::from_secs(ROOM_ACCESS_LIFETIME),
+        };
+
+        let result = diesel::insert_into(room_access_tokens::table)
+            .values(&new_token)
+            .get_result(conn);
+
+        match result {
+            Ok(access_token) => Ok(access_token),
+            Err(e) => {
+                error!("Error while creating a room access token: {}", e);
+                Err(Failure(Status::InternalServerError))
+            }
+        }
+    }
+
+    // Check that a token was handed out for this room and hasn't expired yet
+    #[inline]
+    pub fn is_valid(conn: &PgConnection, r_id: i64, access_token: &str) -> bool {
+        use diesel::dsl::exists;
+        use diesel::prelude::*;
+        use schema::room_access_tokens::dsl::*;
+
+        diesel::select(exists(
+            room_access_tokens
+                .filter(token.eq(access_token))
+                .filter(room_id.eq(r_id))
+                .filter(expires_on.gt(SystemTime::now())),
+        ))
+        .get_result(conn)
+        .unwrap_or(false)
+    }
+
+    // Revoke every token of a room, used when its password changes
+    #[inline]
+    pub fn delete_all(conn: &PgConnection, r_id: i64) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::room_access_tokens::dsl::*;
+
+        let result = diesel::delete(room_access_tokens.filter(room_id.eq(r_id))).execute(conn);
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Failure(Status::InternalServerError)),
+        }
+    }
+}
+
+// Invite tokens are read from the X-Invite-Token header or the `invite` query parameter,
+// so invite links and <img> tags work without custom headers.
+fn invite_token(request: &Request) -> Option<String> {
+    if let Some(token) = request.headers().get_one("X-Invite-Token") {
+        return Some(token.to_string());
+    }
+
+    let query = request.uri().query()?;
+
+    for (key, value) in FormItems::from(query) {
+        if key.as_str() == "invite" {
+            return value.url_decode().ok();
+        }
+    }
+
+    None
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for BearerToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<BearerToken, ()> {
+        match request.headers().get_one("Authorization") {
+            Some(header) if header.starts_with("Bearer ") => {
+                Outcome::Success(BearerToken(header["Bearer ".len()..].trim().to_string()))
+            }
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AuthUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AuthUser, ()> {
+        let token = request.guard::<BearerToken>()?;
+        let conn = request.guard::<DbConn>()?;
+
+        match Session::user(&conn, &token.0) {
+            Some(user) => Outcome::Success(AuthUser(user)),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AdminUser, ()> {
+        let user = request.guard::<AuthUser>()?;
+
+        if user.0.is_admin {
+            Outcome::Success(AdminUser(user.0))
+        } else {
+            Outcome::Failure((Status::Forbidden, ()))
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RoomCredentials {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RoomCredentials, ()> {
+        let user = request.guard::<AuthUser>().succeeded().map(|user| user.0);
+
+        Outcome::Success(RoomCredentials {
+            user,
+            invite_token: invite_token(request),
+            access_token: request
+                .headers()
+                .get_one("X-Room-Token")
+                .map(|token| token.to_string()),
+        })
+    }
+}
@@ -0,0 +1,305 @@
+use diesel::pg::PgConnection;
+use rocket::config::{Config, RocketConfig};
+use serde_json;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use error::ApiError;
+use export::{RoomExport, RoomImport};
+use migrations;
+use names::NameRules;
+use room::{Room, RoomPatch};
+use storage::Storage;
+use user::{NewUser, User};
+use video::Video;
+use youtube::YoutubeVideo;
+use {establish_connection, init_pool};
+
+const USAGE: &str = "Usage: youkebox [command]
+
+Starts the server when no command is given.
+
+Commands:
+    user create <username> [--admin]   Create a user, the password is read from stdin
+    user promote <username>            Make a user an admin
+    rooms list                         List every room, including archived and deleted ones
+    rooms rename <id> <name>           Rename a room
+    rooms delete <id>                  Delete a room, it can be restored until it gets purged
+    rooms export <id>                  Write a room with its settings, picture, queue and history to stdout
+    rooms import [options]             Create a room from an export read from stdin
+        --name <name>                  Use another name than the exported one
+        --owner <username>             Use another owner than the exported one
+    videos purge <days>                Remove the videos played more than <days> days ago
+    videos refresh [<room id>]         Fetch the title, description, duration and channel of stored videos again
+    migrate status                     List every migration and whether it was applied
+    migrate run                        Apply the pending migrations
+    migrate revert                     Revert the migration that was applied last, needs the migrations/ directory
+
+Settings like the name rules and the YouTube API key are read from Rocket.toml.
+";
+
+/// Runs a `youkebox <command>` and returns the exit code
+pub fn run(args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        ["user", "create", username] => create_user(username, false),
+        ["user", "create", username, "--admin"] => create_user(username, true),
+        ["user", "promote", username] => promote_user(username),
+        ["rooms", "list"] => list_rooms(),
+        ["rooms", "rename", id, name] => rename_room(id, name),
+        ["rooms", "delete", id] => delete_room(id),
+        ["rooms", "export", id] => export_room(id),
+        ["videos", "purge", days] => purge_videos(days),
+        ["videos", "refresh"] => refresh_videos(None),
+        ["videos", "refresh", id] => refresh_videos(Some(id)),
+        ["migrate", "status"] => migrate_status(),
+        ["migrate", "run"] => migrate_run(),
+        ["migrate", "revert"] => migrate_revert(),
+        _ if args.starts_with(&["rooms", "import"]) => import_room(&args[2..]),
+        ["help"] | ["--help"] | ["-h"] => {
+            print!("{}", USAGE);
+            return 0;
+        }
+        _ => {
+            eprint!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+// The same Rocket.toml the server reads, for the environment in ROCKET_ENV
+fn config() -> Result<Config, String> {
+    RocketConfig::read()
+        .map(|config| config.active().clone())
+        .map_err(|e| format!("Unable to read Rocket.toml: {}", e))
+}
+
+fn fail<E: Into<ApiError>>(e: E) -> String {
+    e.into().to_string()
+}
+
+fn parse_id(id: &str) -> Result<i64, String> {
+    id.parse().map_err(|_| format!("'{}' is not a room id", id))
+}
+
+fn find_room(conn: &PgConnection, id: &str) -> Result<Room, String> {
+    let room_id = parse_id(id)?;
+    Room::find(conn, room_id).ok_or_else(|| format!("Room {} doesn't exist or was deleted", room_id))
+}
+
+fn find_user(conn: &PgConnection, username: &str) -> Result<User, String> {
+    User::find_by_name(conn, username).ok_or_else(|| format!("User '{}' doesn't exist", username))
+}
+
+fn create_user(username: &str, admin: bool) -> Result<(), String> {
+    let rules = NameRules::from_config(&config()?);
+
+    let username = User::verify_name(&rules, username).map_err(|e| format!("username: {}", e))?;
+
+    eprint!("Password: ");
+    let mut password = String::new();
+    io::stdin()
+        .read_line(&mut password)
+        .map_err(|e| format!("Unable to read the password: {}", e))?;
+    let password = password.trim_right_matches(|c| c == '\r' || c == '\n').to_string();
+
+    User::verify_password(&password).map_err(|e| format!("password: {}", e))?;
+
+    let conn = establish_connection();
+
+    let mut user = User::create(
+        &conn,
+        NewUser {
+            username,
+            password,
+            username_skeleton: None,
+        },
+    ).map_err(fail)?;
+
+    if admin {
+        user = User::set_admin(&conn, user.id, true).map_err(fail)?;
+    }
+
+    let role = if user.is_admin { "admin" } else { "user" };
+    println!("Created {} {} ({})", role, user.username, user.id);
+    Ok(())
+}
+
+fn promote_user(username: &str) -> Result<(), String> {
+    let conn = establish_connection();
+
+    let user = find_user(&conn, username)?;
+    let user = User::set_admin(&conn, user.id, true).map_err(fail)?;
+
+    println!("{} ({}) is an admin now", user.username, user.id);
+    Ok(())
+}
+
+fn list_rooms() -> Result<(), String> {
+    let conn = establish_connection();
+
+    for room in Room::all(&conn).map_err(fail)? {
+        let state = if room.deleted_at.is_some() {
+            "deleted"
+        } else if room.archived_at.is_some() {
+            "archived"
+        } else {
+            "active"
+        };
+        let visibility = if room.is_public { "public" } else { "private" };
+
+        println!(
+            "{:>6}  {:<8}  {:<7}  {:<24}  {}",
+            room.id, state, visibility, room.slug, room.name
+        );
+    }
+
+    Ok(())
+}
+
+fn rename_room(id: &str, name: &str) -> Result<(), String> {
+    let rules = NameRules::from_config(&config()?);
+    let conn = establish_connection();
+
+    let room = find_room(&conn, id)?;
+
+    let patch = RoomPatch {
+        name: Some(name.to_string()),
+        description: None,
+        is_public: None,
+        slug: None,
+        name_skeleton: None,
+    };
+
+    let room = Room::update(&conn, &rules, &room, patch).map_err(fail)?;
+
+    println!("Renamed room {} to {} ({})", room.id, room.name, room.slug);
+    Ok(())
+}
+
+fn delete_room(id: &str) -> Result<(), String> {
+    let conn = establish_connection();
+
+    let room = find_room(&conn, id)?;
+    Room::delete(&conn, room.id).map_err(fail)?;
+
+    println!("Deleted room {}, it can be restored until it gets purged", room.id);
+    Ok(())
+}
+
+fn export_room(id: &str) -> Result<(), String> {
+    let storage = Storage::from_config(&config()?, &init_pool());
+    let conn = establish_connection();
+
+    let room = find_room(&conn, id)?;
+    let export = RoomExport::create(&conn, &*storage.0, &room).map_err(fail)?;
+
+    let json = serde_json::to_string_pretty(&export).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+fn import_room(options: &[&str]) -> Result<(), String> {
+    let mut name = None;
+    let mut owner = None;
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match (*option, options.next()) {
+            ("--name", Some(value)) => name = Some(value.to_string()),
+            ("--owner", Some(value)) => owner = Some(value.to_string()),
+            _ => return Err(format!("Unknown option '{}', see youkebox help", option)),
+        }
+    }
+
+    let config = config()?;
+    let rules = NameRules::from_config(&config);
+    let storage = Storage::from_config(&config, &init_pool());
+
+    let mut import = RoomImport::read(io::stdin()).map_err(fail)?;
+
+    if let Some(name) = name {
+        import.room.name = name;
+    }
+
+    let conn = establish_connection();
+
+    let owner_id = match owner.or_else(|| import.room.owner.clone()) {
+        Some(owner) => Some(find_user(&conn, &owner)?.id),
+        None => None,
+    };
+
+    let room = import.import(&conn, &*storage.0, &rules, owner_id).map_err(fail)?;
+
+    println!("Imported room {} ({})", room.id, room.slug);
+    Ok(())
+}
+
+fn purge_videos(days: &str) -> Result<(), String> {
+    let days: u64 = days
+        .parse()
+        .map_err(|_| format!("'{}' is not an amount of days", days))?;
+
+    let conn = establish_connection();
+
+    let before = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+    let purged = Video::purge_played(&conn, before).map_err(fail)?;
+
+    println!("Removed {} played videos", purged);
+    Ok(())
+}
+
+fn refresh_videos(id: Option<&str>) -> Result<(), String> {
+    let config = config()?;
+    let api_key = config
+        .get_str("YOUTUBE_API_KEY")
+        .map_err(|_| "YOUTUBE_API_KEY not set in Rocket.toml".to_string())?;
+
+    let conn = establish_connection();
+
+    let room_id = match id {
+        Some(id) => Some(find_room(&conn, id)?.id),
+        None => None,
+    };
+
+    let updated = YoutubeVideo::refresh(api_key, &conn, room_id).map_err(fail)?;
+
+    println!("Updated {} videos", updated);
+    Ok(())
+}
+
+fn migrate_status() -> Result<(), String> {
+    let conn = establish_connection();
+
+    let status = migrations::status(&conn).map_err(|e| e.to_string())?;
+
+    for migration in status {
+        let applied = if migration.applied { "X" } else { " " };
+        println!("[{}] {}_{}", applied, migration.version, migration.name);
+    }
+
+    Ok(())
+}
+
+fn migrate_run() -> Result<(), String> {
+    let conn = establish_connection();
+
+    migrations::run_pending(&conn, &mut io::stdout()).map_err(|e| e.to_string())
+}
+
+fn migrate_revert() -> Result<(), String> {
+    let conn = establish_connection();
+
+    migrations::revert_latest(&conn)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
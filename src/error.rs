@@ -0,0 +1,144 @@
+use diesel;
+use reqwest;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Failure, Responder, Response};
+use rocket_contrib::Json;
+use std::fmt;
+
+use http::{status_code, HttpStatus};
+use validation::ValidationErrors;
+
+/// Everything a route can fail with, answered with a HttpStatus body.
+/// Model functions that still return a `Failure` convert into it with `?`.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The details are logged, the client only gets a 500
+    Database(diesel::result::Error),
+    /// The YouTube API couldn't be reached or sent something unexpected, answered with a 502
+    Youtube(String),
+    /// Rejected fields, answered with a 422 unless another status was set
+    Validation(ValidationErrors),
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    /// Any other status
+    Status(Status),
+}
+
+impl ApiError {
+    pub fn status(&self) -> Status {
+        match *self {
+            ApiError::Database(_) => Status::InternalServerError,
+            ApiError::Youtube(_) => Status::BadGateway,
+            ApiError::Validation(ref errors) => {
+                Status::from_code(errors.status).unwrap_or(Status::UnprocessableEntity)
+            }
+            ApiError::Unauthorized => Status::Unauthorized,
+            ApiError::Forbidden => Status::Forbidden,
+            ApiError::NotFound => Status::NotFound,
+            ApiError::Status(status) => status,
+        }
+    }
+
+    pub fn code(&self) -> String {
+        match *self {
+            ApiError::Database(_) => "database_error".to_string(),
+            ApiError::Youtube(_) => "youtube_error".to_string(),
+            ApiError::Validation(_) => "validation_failed".to_string(),
+            _ => status_code(self.status()),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match *self {
+            ApiError::Youtube(_) => "Unable to reach YouTube".to_string(),
+            ApiError::Validation(ref errors) => errors.message.to_string(),
+            ApiError::NotFound => "The requested resource was not found".to_string(),
+            _ => self.status().reason.to_string(),
+        }
+    }
+}
+
+// Used by the command line, which has nobody to hide the details from
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApiError::Database(ref e) => write!(f, "Database error: {}", e),
+            ApiError::Youtube(ref e) => write!(f, "YouTube error: {}", e),
+            ApiError::Validation(ref errors) => {
+                write!(f, "{}", errors.message)?;
+                for error in &errors.errors {
+                    write!(f, "\n    {}: {}", error.field, error.message)?;
+                }
+                Ok(())
+            }
+            _ => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> ApiError {
+        match status.code {
+            401 => ApiError::Unauthorized,
+            403 => ApiError::Forbidden,
+            404 => ApiError::NotFound,
+            _ => ApiError::Status(status),
+        }
+    }
+}
+
+impl From<Failure> for ApiError {
+    fn from(failure: Failure) -> ApiError {
+        ApiError::from(failure.0)
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> ApiError {
+        ApiError::Validation(errors)
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> ApiError {
+        match e {
+            diesel::result::Error::NotFound => ApiError::NotFound,
+            e => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> ApiError {
+        // The url of the request contains the API key, so leave it out
+        match e.get_ref() {
+            Some(cause) => ApiError::Youtube(cause.to_string()),
+            None => ApiError::Youtube("The request failed".to_string()),
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        match self {
+            ApiError::Database(ref e) => error!("Database error: {}", e),
+            ApiError::Youtube(ref e) => error!("YouTube error: {}", e),
+            _ => {}
+        }
+
+        let status = self.status();
+
+        let mut body = HttpStatus::new(status, &self.message()).for_request(req);
+        body.code = self.code();
+
+        if let ApiError::Validation(errors) = self {
+            body.errors = errors.errors;
+        }
+
+        Response::build_from(Json(body).respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
@@ -0,0 +1,339 @@
+use base64;
+use diesel;
+use diesel::pg::PgConnection;
+use rocket::http::Status;
+use serde_json::{self, Value};
+use std::io::Read;
+use std::time::SystemTime;
+
+use error::ApiError;
+use names::NameRules;
+use picture::{self, MAX_UPLOAD_SIZE, PICTURE_TYPE};
+use room::{NewRoom, Room};
+use schema::videos;
+use settings::{RoomSettings, RoomSettingsPatch};
+use storage::PictureStorage;
+use user::User;
+use player::duration_to_seconds;
+use validation::ValidationErrors;
+use video::Video;
+use youtube::is_video_id;
+
+/// Bumped whenever the format changes in a way older versions can't read
+pub const EXPORT_VERSION: u64 = 1;
+
+/// Largest export that can be imported, it's mostly the picture and the history
+pub const MAX_IMPORT_SIZE: u64 = 32 * 1024 * 1024;
+
+/// A room together with its settings, picture, queue and history.
+/// Invite tokens and passwords are left out, they don't belong in backups.
+#[derive(Serialize)]
+pub struct RoomExport {
+    pub version: u64,
+    pub exported_at: SystemTime,
+    pub room: ExportedRoom,
+    pub settings: RoomSettings,
+    pub picture: Option<ExportedPicture>,
+    pub queue: Vec<ExportedVideo>,
+    pub history: Vec<ExportedVideo>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedRoom {
+    pub name: String,
+    pub description: Option<String>,
+    pub is_public: bool,
+    /// The username of the owner
+    pub owner: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedPicture {
+    pub content_type: String,
+    /// The picture itself, base64 encoded
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedVideo {
+    pub video_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub duration: String,
+    pub added_on: SystemTime,
+    pub started_on: Option<SystemTime>,
+    pub channel: Option<String>,
+    pub skipped_on: Option<SystemTime>,
+}
+
+/// A RoomExport as it's read back, the settings are validated like a settings update
+#[derive(Deserialize)]
+pub struct RoomImport {
+    pub version: u64,
+    pub room: ExportedRoom,
+    #[serde(default)]
+    pub settings: RoomSettingsPatch,
+    pub picture: Option<ExportedPicture>,
+    #[serde(default)]
+    pub queue: Vec<ExportedVideo>,
+    #[serde(default)]
+    pub history: Vec<ExportedVideo>,
+}
+
+#[derive(Insertable)]
+#[table_name = "videos"]
+struct ImportedVideo<'a> {
+    video_id: &'a str,
+    title: &'a str,
+    description: Option<&'a str>,
+    room_id: i64,
+    duration: &'a str,
+    played: bool,
+    added_on: SystemTime,
+    started_on: Option<SystemTime>,
+    channel: Option<&'a str>,
+    skipped_on: Option<SystemTime>,
+}
+
+impl<'a> From<&'a Video> for ExportedVideo {
+    fn from(video: &Video) -> ExportedVideo {
+        ExportedVideo {
+            video_id: video.video_id.clone(),
+            title: video.title.clone(),
+            description: video.description.clone(),
+            duration: video.duration.clone(),
+            added_on: video.added_on,
+            started_on: video.started_on,
+            channel: video.channel.clone(),
+            skipped_on: video.skipped_on,
+        }
+    }
+}
+
+impl RoomExport {
+    #[inline]
+    pub fn create(
+        conn: &PgConnection,
+        storage: &PictureStorage,
+        room: &Room,
+    ) -> Result<RoomExport, ApiError> {
+        use diesel::prelude::*;
+        use schema::videos::dsl::*;
+
+        let owner = match room.owner_id {
+            Some(owner_id) => Some(User::find(conn, owner_id)?.username),
+            None => None,
+        };
+
+        let picture = match room.picture_path {
+            Some(ref path) => storage.get(path)?.map(|data| ExportedPicture {
+                content_type: room
+                    .picture_type
+                    .clone()
+                    .unwrap_or_else(|| PICTURE_TYPE.to_string()),
+                data: base64::encode(&data),
+            }),
+            None => None,
+        };
+
+        let room_videos = Video::belonging_to(room).order(id).load::<Video>(conn)?;
+
+        let (history, queue): (Vec<&Video>, Vec<&Video>) =
+            room_videos.iter().partition(|video| video.played);
+
+        Ok(RoomExport {
+            version: EXPORT_VERSION,
+            exported_at: SystemTime::now(),
+            room: ExportedRoom {
+                name: room.name.clone(),
+                description: room.description.clone(),
+                is_public: room.is_public,
+                owner,
+            },
+            settings: RoomSettings::find(conn, room.id)?,
+            picture,
+            queue: queue.into_iter().map(ExportedVideo::from).collect(),
+            history: history.into_iter().map(ExportedVideo::from).collect(),
+        })
+    }
+}
+
+impl RoomImport {
+    /// Reads an export of at most MAX_IMPORT_SIZE bytes, larger ones are rejected with a 413
+    pub fn read<R: Read>(export: R) -> Result<RoomImport, ApiError> {
+        let mut json = String::new();
+
+        match export.take(MAX_IMPORT_SIZE + 1).read_to_string(&mut json) {
+            Ok(_) if json.len() as u64 > MAX_IMPORT_SIZE => Err(Status::PayloadTooLarge.into()),
+            Ok(_) => RoomImport::parse(&json),
+            Err(e) => Err(invalid("body", &format!("Is not a valid room export: {}", e))),
+        }
+    }
+
+    /// Exports of another version are rejected before the rest gets a look
+    pub fn parse(json: &str) -> Result<RoomImport, ApiError> {
+        let value: Value = serde_json::from_str(json).map_err(invalid_json)?;
+
+        match value.get("version").and_then(Value::as_u64) {
+            Some(EXPORT_VERSION) => {}
+            Some(version) => {
+                return Err(invalid(
+                    "version",
+                    &format!("Unsupported export version {}, expected {}.", version, EXPORT_VERSION),
+                ))
+            }
+            None => return Err(invalid("version", "Is missing.")),
+        }
+
+        serde_json::from_value(value).map_err(invalid_json)
+    }
+
+    /// Checks the songs of the queue and history the way YouTube would have described them,
+    /// the player and the statistics rely on their ids and durations
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let mut errors = ValidationErrors::new();
+
+        if let Err(invalid) = self.settings.apply(&mut RoomSettings::default_for(0)) {
+            for error in invalid.errors {
+                errors.add(&format!("settings.{}", error.field), &error.message);
+            }
+        }
+
+        let lists = [("queue", &self.queue), ("history", &self.history)];
+
+        for &(list, songs) in &lists {
+            for (index, song) in songs.iter().enumerate() {
+                if !is_video_id(&song.video_id) {
+                    errors.add(
+                        &format!("{}[{}].video_id", list, index),
+                        "Is not a YouTube video id.",
+                    );
+                }
+
+                if song.title.trim().is_empty() {
+                    errors.add(&format!("{}[{}].title", list, index), "Can't be empty.");
+                }
+
+                if duration_to_seconds(&song.duration).is_none() {
+                    errors.add(
+                        &format!("{}[{}].duration", list, index),
+                        "Is not an ISO 8601 duration, eg: PT3M10S.",
+                    );
+                }
+            }
+        }
+
+        errors.into_result().map_err(Into::into)
+    }
+
+    /// Creates a new room with the settings, picture, queue and history of the export.
+    /// The room is checked like any other new room, so its name has to be available.
+    #[inline]
+    pub fn import(
+        self,
+        conn: &PgConnection,
+        storage: &PictureStorage,
+        rules: &NameRules,
+        owner_id: Option<i64>,
+    ) -> Result<Room, ApiError> {
+        use diesel::prelude::*;
+
+        if !self.room.is_public && owner_id.is_none() {
+            return Err(invalid("room.is_public", "Only rooms with an owner can be private."));
+        }
+
+        self.validate()?;
+
+        // The picture goes through the same pipeline as an upload, before anything gets stored
+        let processed = match self.picture {
+            Some(ref exported) => {
+                let data = base64::decode(&exported.data)
+                    .map_err(|_| invalid("picture.data", "Is not valid base64."))?;
+
+                if data.len() as u64 > MAX_UPLOAD_SIZE {
+                    return Err(invalid("picture.data", "Is too large."));
+                }
+
+                let processed = picture::process(&data)
+                    .map_err(|_| invalid("picture.data", "Is not a supported image."))?;
+
+                Some(processed)
+            }
+            None => None,
+        };
+
+        // The picture storage isn't part of the transaction
+        let mut created = None;
+
+        let result = conn.transaction(|| {
+            let room = Room::create(
+                conn,
+                rules,
+                NewRoom {
+                    name: self.room.name.clone(),
+                    description: self.room.description.clone(),
+                    is_public: self.room.is_public,
+                    owner_id,
+                    slug: String::new(),
+                    name_skeleton: None,
+                },
+            )?;
+            created = Some(room.id);
+
+            RoomSettings::update(conn, room.id, &self.settings)?;
+
+            // The history goes first, the player plays the unplayed video with the lowest id
+            {
+                let history = self.history.iter().map(|video| imported(video, room.id, true));
+                let queue = self.queue.iter().map(|video| imported(video, room.id, false));
+                let new_videos: Vec<ImportedVideo> = history.chain(queue).collect();
+
+                diesel::insert_into(videos::table)
+                    .values(&new_videos)
+                    .execute(conn)?;
+            }
+
+            if let Some(ref processed) = processed {
+                let picture_path = picture::save(storage, room.id, processed)?;
+                Room::set_picture(conn, room.id, Some((&picture_path, PICTURE_TYPE)))?;
+            }
+
+            Room::find(conn, room.id).ok_or(ApiError::NotFound)
+        });
+
+        // The room was rolled back, so is the picture it may have stored
+        if let (true, Some(room_id)) = (result.is_err() && processed.is_some(), created) {
+            if let Err(e) = picture::remove(storage, &picture::picture_path(room_id)) {
+                error!("Unable to remove the picture of a failed import: {:?}", e);
+            }
+        }
+
+        result
+    }
+}
+
+// Queued videos start from the beginning once the room plays again
+fn imported(video: &ExportedVideo, room_id: i64, played: bool) -> ImportedVideo {
+    ImportedVideo {
+        video_id: &video.video_id,
+        title: &video.title,
+        description: video.description.as_ref().map(String::as_str),
+        room_id,
+        duration: &video.duration,
+        played,
+        added_on: video.added_on,
+        started_on: if played { video.started_on } else { None },
+        channel: video.channel.as_ref().map(String::as_str),
+        skipped_on: if played { video.skipped_on } else { None },
+    }
+}
+
+fn invalid(field: &str, message: &str) -> ApiError {
+    let mut errors = ValidationErrors::new();
+    errors.add(field, message);
+    errors.into()
+}
+
+fn invalid_json(e: serde_json::Error) -> ApiError {
+    invalid("body", &format!("Is not a valid room export: {}", e))
+}
@@ -0,0 +1,236 @@
+use diesel;
+use diesel::pg::PgConnection;
+use reqwest::header::{Authorization, Bearer, Headers};
+use reqwest::{self, Method};
+use rocket::config::Config;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use std::io::Read;
+use std::time::SystemTime;
+
+use error::ApiError;
+use metrics::{self, YoutubeCall};
+use room::Room;
+use schema::favourites;
+use user::User;
+use video::Video;
+use validation::ValidationErrors;
+use API_URL;
+
+/// A song a user liked while it was playing in a room
+#[derive(Serialize, Queryable, Associations)]
+#[belongs_to(User)]
+pub struct Favourite {
+    #[serde(skip_serializing)]
+    pub user_id: i64,
+    pub video_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub duration: String,
+    /// The room it was playing in, None once that room is purged
+    pub room_id: Option<i64>,
+    pub added_on: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "favourites"]
+struct NewFavourite<'a> {
+    user_id: i64,
+    video_id: &'a str,
+    title: &'a str,
+    description: Option<&'a str>,
+    duration: &'a str,
+    room_id: i64,
+}
+
+impl Favourite {
+    /// Adds the song the room is playing right now, liking a song twice keeps the first one
+    #[inline]
+    pub fn add_now_playing(conn: &PgConnection, user: &User, room: &Room) -> Result<Favourite, ApiError> {
+        use diesel::prelude::*;
+        use schema::favourites::dsl::*;
+        use schema::videos;
+
+        let playing = Video::belonging_to(room)
+            .filter(videos::played.eq(false))
+            .filter(videos::started_on.is_not_null())
+            .order(videos::id)
+            .first::<Video>(conn)
+            .optional()?;
+
+        let playing = match playing {
+            Some(playing) => playing,
+            None => {
+                let mut errors = ValidationErrors::new();
+                errors.add("room", "Isn't playing anything.");
+                return Err(errors.into());
+            }
+        };
+
+        diesel::insert_into(favourites)
+            .values(&NewFavourite {
+                user_id: user.id,
+                video_id: &playing.video_id,
+                title: &playing.title,
+                description: playing.description.as_ref().map(String::as_str),
+                duration: &playing.duration,
+                room_id: room.id,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        favourites
+            .find((user.id, &playing.video_id))
+            .first::<Favourite>(conn)
+            .map_err(Into::into)
+    }
+
+    /// The favourites of a user, the most recent one first
+    #[inline]
+    pub fn list(conn: &PgConnection, u_id: i64) -> Result<Vec<Favourite>, ApiError> {
+        use diesel::prelude::*;
+        use schema::favourites::dsl::*;
+
+        favourites
+            .filter(user_id.eq(u_id))
+            .order(added_on.desc())
+            .load::<Favourite>(conn)
+            .map_err(Into::into)
+    }
+
+    #[inline]
+    pub fn remove(conn: &PgConnection, u_id: i64, v_id: &str) -> Result<(), ApiError> {
+        use diesel::prelude::*;
+        use schema::favourites::dsl::*;
+
+        match diesel::delete(favourites.find((u_id, v_id))).execute(conn)? {
+            0 => Err(ApiError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The body of an export, the access token comes from an OAuth flow the client went through
+/// with the https://www.googleapis.com/auth/youtube scope
+#[derive(Deserialize)]
+pub struct FavouritesExport {
+    pub access_token: String,
+    pub title: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ExportedPlaylist {
+    pub playlist_id: String,
+    pub url: String,
+    pub videos: usize,
+}
+
+#[derive(Deserialize)]
+struct CreatedResource {
+    id: String,
+}
+
+/// Creates playlists on the YouTube account of a user, with their OAuth access token
+/// instead of the API key
+pub struct YoutubePlaylists {
+    pub api_url: String,
+}
+
+impl YoutubePlaylists {
+    /// YOUTUBE_OAUTH_API_URL defaults to the YouTube Data API, it's only worth changing for a stub
+    pub fn from_config(config: &Config) -> YoutubePlaylists {
+        YoutubePlaylists {
+            api_url: config
+                .get_str("YOUTUBE_OAUTH_API_URL")
+                .unwrap_or(*API_URL)
+                .trim_right_matches('/')
+                .to_string(),
+        }
+    }
+
+    /// Creates a private playlist and adds every favourite to it, oldest first
+    pub fn export(
+        &self,
+        access_token: &str,
+        title: &str,
+        songs: &[Favourite],
+    ) -> Result<ExportedPlaylist, ApiError> {
+        let playlist: CreatedResource = self.insert(
+            access_token,
+            "playlists",
+            &json!({
+                "snippet": {
+                    "title": title,
+                    "description": "Favourites from The YoukeBox",
+                },
+                "status": { "privacyStatus": "private" },
+            }),
+        )?;
+
+        for song in songs.iter().rev() {
+            let _: CreatedResource = self.insert(
+                access_token,
+                "playlistItems",
+                &json!({
+                    "snippet": {
+                        "playlistId": playlist.id,
+                        "resourceId": { "kind": "youtube#video", "videoId": song.video_id },
+                    },
+                }),
+            )?;
+        }
+
+        Ok(ExportedPlaylist {
+            url: format!("https://www.youtube.com/playlist?list={}", playlist.id),
+            playlist_id: playlist.id,
+            videos: songs.len(),
+        })
+    }
+
+    fn insert<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        resource: &str,
+        body: &Value,
+    ) -> Result<T, ApiError> {
+        let result = self.insert_unrecorded(access_token, resource, body);
+        metrics::record(|metrics| metrics.youtube_call(YoutubeCall::Playlists, result.is_ok()));
+        result
+    }
+
+    fn insert_unrecorded<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        resource: &str,
+        body: &Value,
+    ) -> Result<T, ApiError> {
+        let url = format!("{}/{}?part=snippet,status", self.api_url, resource);
+
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer {
+            token: access_token.to_string(),
+        }));
+
+        let mut resp = reqwest::Client::new()
+            .request(Method::Post, &url)
+            .headers(headers)
+            .json(body)
+            .send()?;
+
+        // An expired or revoked token, the client has to go through OAuth again
+        if resp.status().as_u16() == 401 {
+            return Err(ApiError::Unauthorized);
+        }
+
+        if !resp.status().is_success() {
+            return Err(ApiError::Youtube(format!("YouTube answered with {}", resp.status())));
+        }
+
+        let mut content = String::new();
+        resp.read_to_string(&mut content)
+            .map_err(|e| ApiError::Youtube(e.to_string()))?;
+
+        serde_json::from_str(&content)
+            .map_err(|e| ApiError::Youtube(format!("Unexpected {} result: {}", resource, e)))
+    }
+}
@@ -0,0 +1,138 @@
+use diesel::pg::PgConnection;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use migrations;
+use player;
+use youtube::ApiKey;
+use Pool;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Check {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Check {
+    pub fn ok() -> Check {
+        Check {
+            status: "ok",
+            message: None,
+        }
+    }
+
+    pub fn fail(message: &str) -> Check {
+        Check {
+            status: "fail",
+            message: Some(message.to_string()),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// The result of every readiness check, keyed by the name of the check
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Readiness {
+    pub fn new(checks: BTreeMap<&'static str, Check>) -> Readiness {
+        let status = if checks.values().all(Check::is_ok) {
+            "ok"
+        } else {
+            "fail"
+        };
+
+        Readiness { status, checks }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Checks whether we can serve requests: the database is reachable and up to date,
+/// the players are running and we're able to talk to YouTube
+pub fn readiness(pool: &Pool, api_key: &ApiKey) -> Readiness {
+    let mut checks = BTreeMap::new();
+
+    match pool.get() {
+        Ok(conn) => {
+            checks.insert("database", Check::ok());
+            checks.insert("migrations", check_migrations(&conn));
+        }
+        Err(e) => {
+            error!("Unable to get a database connection: {}", e);
+            checks.insert("database", Check::fail("Unable to get a database connection"));
+            checks.insert("migrations", Check::fail("The database is unreachable"));
+        }
+    }
+
+    checks.insert(
+        "player",
+        check_player(
+            player::is_running(),
+            player::since_last_sync(),
+            player::since_last_panic(),
+        ),
+    );
+
+    let youtube_api_key = if api_key.0.trim().is_empty() {
+        Check::fail("YOUTUBE_API_KEY is empty")
+    } else {
+        Check::ok()
+    };
+    checks.insert("youtube_api_key", youtube_api_key);
+
+    Readiness::new(checks)
+}
+
+/// The players are healthy when their lock isn't poisoned, they were synced with the database
+/// during the last few sync intervals and none of them panicked in that time.
+/// Players that panicked are started again by the next sync.
+pub fn check_player(running: bool, since_sync: Option<Duration>, since_panic: Option<Duration>) -> Check {
+    let window = Duration::from_secs(3 * player::PLAYER_SYNC_INTERVAL);
+
+    if !running {
+        return Check::fail("A player thread panicked, rooms can no longer be started or stopped");
+    }
+
+    let since_sync = match since_sync {
+        Some(since_sync) => since_sync,
+        None => return Check::fail("The players were never synced"),
+    };
+
+    if since_sync > window {
+        return Check::fail(&format!(
+            "The players weren't synced for {} seconds",
+            since_sync.as_secs()
+        ));
+    }
+
+    match since_panic {
+        Some(since_panic) if since_panic <= window => Check::fail(&format!(
+            "A player thread panicked {} seconds ago",
+            since_panic.as_secs()
+        )),
+        _ => Check::ok(),
+    }
+}
+
+/// Compares the embedded migrations with the ones that were applied
+pub fn check_migrations(conn: &PgConnection) -> Check {
+    match migrations::pending(conn) {
+        Ok(ref pending) if pending.is_empty() => Check::ok(),
+        Ok(ref pending) if pending.len() == 1 => Check::fail("1 migration is pending"),
+        Ok(pending) => Check::fail(&format!("{} migrations are pending", pending.len())),
+        Err(e) => {
+            error!("Unable to load the applied migrations: {}", e);
+            Check::fail("Unable to load the applied migrations")
+        }
+    }
+}
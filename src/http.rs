@@ -1,5 +1,55 @@
+use rocket::http::Status;
+use rocket::request::Request;
+
+use validation::FieldError;
+
+/// Set by the client or a proxy in front of us to tell requests apart in the logs
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The JSON body of plain status responses and of every error
 #[derive(Serialize)]
 pub struct HttpStatus {
     pub status: u16,
+    /// Machine readable version of the message, eg: "not_found" or "validation_failed"
+    pub code: String,
     pub message: String,
+    /// One entry per rejected field, only present for validation errors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl HttpStatus {
+    pub fn new(status: Status, message: &str) -> HttpStatus {
+        HttpStatus {
+            status: status.code,
+            code: status_code(status),
+            message: message.to_string(),
+            errors: Vec::new(),
+            request_id: None,
+        }
+    }
+
+    /// Adds the id of the request this is an answer to
+    pub fn for_request(mut self, req: &Request) -> HttpStatus {
+        self.request_id = request_id(req);
+        self
+    }
+}
+
+pub fn request_id(req: &Request) -> Option<String> {
+    req.headers()
+        .get_one(REQUEST_ID_HEADER)
+        .map(|request_id| request_id.to_string())
+}
+
+/// The reason phrase of a status in snake case, eg: 404 Not Found becomes "not_found"
+pub fn status_code(status: Status) -> String {
+    status
+        .reason
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
 }
@@ -0,0 +1,136 @@
+use diesel::pg::PgConnection;
+use listener::RoomListener;
+use log::Level;
+use room::Room;
+use storage::Storage;
+use std::time::{Duration, SystemTime};
+use std::{thread, time};
+
+use establish_connection;
+
+// Look for idle and deleted rooms once an hour
+const JANITOR_INTERVAL: u64 = 60 * 60;
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IdleAction {
+    Archive,
+    Delete,
+}
+
+impl IdleAction {
+    pub fn parse(value: &str) -> Option<IdleAction> {
+        match value {
+            "archive" => Some(IdleAction::Archive),
+            "delete" => Some(IdleAction::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Configured through ROOM_IDLE_DAYS, ROOM_IDLE_ACTION and ROOM_DELETE_RETENTION_DAYS in Rocket.toml
+#[derive(Clone, Copy, Debug)]
+pub struct JanitorConfig {
+    /// 0 keeps idle rooms forever
+    pub idle_days: u64,
+    pub action: IdleAction,
+    /// How long a deleted room can still be restored before it gets purged
+    pub retention_days: u64,
+}
+
+impl JanitorConfig {
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_days * DAY)
+    }
+}
+
+/// Archives or deletes every room that has been idle for longer than the configured amount of days.
+/// Returns the amount of cleaned up rooms.
+pub fn clean_idle_rooms(conn: &PgConnection, config: &JanitorConfig) -> usize {
+    if config.idle_days == 0 {
+        return 0;
+    }
+
+    let since = SystemTime::now() - Duration::from_secs(config.idle_days * DAY);
+
+    let idle_rooms = match Room::idle(conn, since) {
+        Ok(idle_rooms) => idle_rooms,
+        Err(_) => return 0,
+    };
+
+    let mut cleaned = 0;
+
+    for room in idle_rooms {
+        let result = match config.action {
+            IdleAction::Archive => Room::archive(conn, &room),
+            IdleAction::Delete => Room::delete(conn, room.id),
+        };
+
+        match result {
+            Ok(_) => {
+                event!(Level::Info, "Cleaned up idle room";
+                    "room_id" => room.id,
+                    "action" => format!("{:?}", config.action)
+                );
+                cleaned += 1;
+            }
+            Err(_) => event!(Level::Error, "Unable to clean up idle room"; "room_id" => room.id),
+        }
+    }
+
+    cleaned
+}
+
+/// Permanently removes every room that was deleted longer ago than the retention period.
+/// Returns the amount of purged rooms.
+pub fn purge_deleted_rooms(conn: &PgConnection, storage: &Storage, config: &JanitorConfig) -> usize {
+    let since = SystemTime::now() - config.retention();
+
+    let deleted_rooms = match Room::deleted_before(conn, since) {
+        Ok(deleted_rooms) => deleted_rooms,
+        Err(_) => return 0,
+    };
+
+    let mut purged = 0;
+
+    for room in deleted_rooms {
+        match Room::purge(conn, storage, room.id) {
+            Ok(_) => {
+                event!(Level::Info, "Purged deleted room"; "room_id" => room.id);
+                purged += 1;
+            }
+            Err(_) => event!(Level::Error, "Unable to purge deleted room"; "room_id" => room.id),
+        }
+    }
+
+    purged
+}
+
+/// Removes the listeners that stopped sending heartbeats, they're no longer counted anyway.
+/// Returns the amount of removed listeners.
+pub fn forget_stale_listeners(conn: &PgConnection) -> usize {
+    match RoomListener::forget_stale(conn) {
+        Ok(forgotten) => forgotten,
+        Err(e) => {
+            event!(Level::Error, "Unable to forget stale listeners"; "error" => e.to_string());
+            0
+        }
+    }
+}
+
+/// Start a thread that periodically cleans up idle rooms, purges deleted ones and
+/// forgets stale listeners
+pub fn start_janitor(config: JanitorConfig, storage: Storage) {
+    thread::Builder::new()
+        .spawn(move || {
+            let conn: PgConnection = establish_connection();
+
+            loop {
+                clean_idle_rooms(&conn, &config);
+                purge_deleted_rooms(&conn, &storage, &config);
+                forget_stale_listeners(&conn);
+                thread::sleep(time::Duration::from_secs(JANITOR_INTERVAL));
+            }
+        })
+        .unwrap();
+}
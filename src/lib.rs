@@ -8,21 +8,31 @@ extern crate diesel;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
+extern crate log;
+#[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
 extern crate rocket_contrib;
 extern crate rocket_cors;
 
+extern crate base64;
 extern crate bcrypt;
-extern crate bytes;
+extern crate chrono;
+extern crate crypto;
+#[macro_use]
+extern crate diesel_migrations;
 extern crate dotenv;
 extern crate image;
 extern crate r2d2;
 extern crate r2d2_diesel;
-extern crate regex;
+extern crate rand;
 extern crate reqwest;
 extern crate rocket;
-extern crate serde_json;
+extern crate serde;
+extern crate unicode_normalization;
+extern crate unicode_skeleton;
 
 use diesel::prelude::*;
 use diesel::pg::PgConnection;
@@ -35,15 +45,38 @@ use rocket::request::{self, FromRequest};
 use rocket::{Outcome, Request, State};
 use diesel::sql_types;
 
+#[macro_use]
+pub mod logging;
 pub mod schema;
+pub mod auth;
+pub mod cli;
+pub mod error;
+pub mod export;
+pub mod favourite;
 pub mod routes;
+pub mod health;
 pub mod http;
 pub mod player;
+pub mod janitor;
+pub mod listener;
 pub mod user;
 pub mod room;
+pub mod saved_playlist;
+pub mod member;
+pub mod metrics;
+pub mod migrations;
+pub mod names;
+pub mod picture;
 pub mod playlist;
+pub mod playlist_file;
 pub mod youtube;
 pub mod video;
+pub mod settings;
+pub mod slug;
+pub mod stats;
+pub mod storage;
+pub mod trending;
+pub mod validation;
 
 pub struct DbConn(pub r2d2::PooledConnection<ConnectionManager<PgConnection>>);
 
@@ -51,7 +84,7 @@ sql_function!(lower, lower_t, (a: sql_types::VarChar) -> sql_types::VarChar);
 
 lazy_static! {
     static ref API_URL: &'static str = "https://www.googleapis.com/youtube/v3";
-    static ref PICTURES_DIR: &'static str = "content/rooms/pictures";
+    pub static ref PICTURES_DIR: &'static str = "content/rooms/pictures";
 }
 
 // Return a single connection from the db pool
@@ -83,7 +116,7 @@ pub fn establish_connection() -> PgConnection {
     PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url))
 }
 
-type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
 pub fn init_pool() -> Pool {
     dotenv().ok();
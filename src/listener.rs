@@ -0,0 +1,312 @@
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use diesel;
+use diesel::pg::PgConnection;
+use rocket::config::Config;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use auth::generate_token;
+use error::ApiError;
+use schema::room_listeners;
+use user::User;
+
+/// Listeners that didn't send a heartbeat for this many seconds have left the room
+pub const LISTENER_TIMEOUT: u64 = 60;
+
+/// Most anonymous listeners a single address counts as in a room, eg: a household or an office
+pub const MAX_ANONYMOUS_PER_ADDRESS: i64 = 10;
+
+/// Configured through LISTENER_SECRET and LISTENER_REAL_IP_HEADER in Rocket.toml
+pub struct ListenerConfig {
+    /// Signs the ids handed out to anonymous listeners
+    secret: Vec<u8>,
+    /// The header a reverse proxy puts the address of the client in, eg: X-Real-IP
+    pub real_ip_header: Option<String>,
+}
+
+/// An anonymous listener as it heartbeats: the id it got from an earlier heartbeat,
+/// sent back in the X-Listener-Id header, and the address it connects from
+pub struct ListenerId {
+    pub id: Option<String>,
+    pub address: String,
+}
+
+#[derive(Queryable)]
+pub struct RoomListener {
+    pub room_id: i64,
+    pub listener_id: String,
+    pub user_id: Option<i64>,
+    pub joined_on: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "room_listeners"]
+struct NewRoomListener<'a> {
+    room_id: i64,
+    listener_id: &'a str,
+    user_id: Option<i64>,
+    joined_on: SystemTime,
+    last_seen: SystemTime,
+}
+
+/// The answer to a heartbeat, anonymous listeners have to send `listener_id` with the next one
+#[derive(Serialize)]
+pub struct Heartbeat {
+    pub listener_id: String,
+    pub listeners: i64,
+    /// Whether this heartbeat was counted. An anonymous listener is only counted once
+    /// it sends back the id it got, and only up to MAX_ANONYMOUS_PER_ADDRESS per address.
+    pub counted: bool,
+}
+
+/// Someone in a room, anonymous listeners don't have a user id or username
+#[derive(Serialize)]
+pub struct Listener {
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    pub joined_on: SystemTime,
+}
+
+#[derive(Serialize)]
+pub struct RoomListeners {
+    pub room_id: i64,
+    pub count: usize,
+    pub anonymous: usize,
+    pub listeners: Vec<Listener>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ListenerId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ListenerId, ()> {
+        let config = request.guard::<State<ListenerConfig>>()?;
+
+        let id = request
+            .headers()
+            .get_one("X-Listener-Id")
+            .map(|listener_id| listener_id.to_string());
+
+        // Without a proxy header the address of the connection is used, a client can't fake that
+        let forwarded = config
+            .real_ip_header
+            .as_ref()
+            .and_then(|header| request.headers().get_one(header))
+            .map(|address| address.trim().to_string());
+
+        let address = forwarded
+            .or_else(|| request.remote().map(|remote| remote.ip().to_string()))
+            .unwrap_or_default();
+
+        Outcome::Success(ListenerId { id, address })
+    }
+}
+
+impl ListenerConfig {
+    pub fn new(secret: &str, real_ip_header: Option<&str>) -> ListenerConfig {
+        ListenerConfig {
+            secret: secret.as_bytes().to_vec(),
+            real_ip_header: real_ip_header.map(String::from),
+        }
+    }
+
+    /// Without a LISTENER_SECRET a random one is used, anonymous ids then change on every restart
+    pub fn from_config(config: &Config) -> ListenerConfig {
+        let secret = match config.get_str("LISTENER_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret.to_string(),
+            _ => generate_token(32),
+        };
+
+        let real_ip_header = config
+            .get_str("LISTENER_REAL_IP_HEADER")
+            .ok()
+            .filter(|header| !header.is_empty());
+
+        ListenerConfig::new(&secret, real_ip_header)
+    }
+
+    /// A new anonymous listener id for a room: the tag of the address, a random part and
+    /// a signature over the room and both of them
+    pub fn issue(&self, room_id: i64, address: &str) -> String {
+        let unsigned = format!("{}.{}", self.address_tag(address), generate_token(16));
+        let signature = self.sign(&format!("{}.{}", room_id, unsigned));
+
+        format!("{}.{}", unsigned, signature)
+    }
+
+    /// Whether the server handed out this id, for this room and to this address
+    pub fn verify(&self, listener_id: &str, room_id: i64, address: &str) -> bool {
+        let parts: Vec<&str> = listener_id.split('.').collect();
+
+        if parts.len() != 3 || parts[0] != self.address_tag(address) {
+            return false;
+        }
+
+        let signature = self.sign(&format!("{}.{}.{}", room_id, parts[0], parts[1]));
+        constant_time_eq(parts[2].as_bytes(), signature.as_bytes())
+    }
+
+    // Identifies the listeners of an address without storing the address itself
+    fn address_tag(&self, address: &str) -> String {
+        self.sign(&format!("address:{}", address))[..16].to_string()
+    }
+
+    fn sign(&self, data: &str) -> String {
+        let mut hmac = Hmac::new(Sha256::new(), &self.secret);
+        hmac.input(data.as_bytes());
+
+        hmac.result()
+            .code()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Heartbeats older than this don't count anymore
+pub fn active_since() -> SystemTime {
+    SystemTime::now() - Duration::from_secs(LISTENER_TIMEOUT)
+}
+
+impl RoomListener {
+    /// Marks someone as listening in a room. Logged in users are counted once, however many
+    /// tabs they have open. Anonymous listeners without a valid id get a new one, they're
+    /// counted from the heartbeat that sends it back.
+    #[inline]
+    pub fn heartbeat(
+        conn: &PgConnection,
+        config: &ListenerConfig,
+        r_id: i64,
+        user: Option<&User>,
+        anonymous: &ListenerId,
+    ) -> Result<Heartbeat, ApiError> {
+        use diesel::prelude::*;
+        use schema::room_listeners::dsl::*;
+
+        let now = SystemTime::now();
+
+        let (id, counted) = match (user, anonymous.id.as_ref()) {
+            (Some(user), _) => (format!("user-{}", user.id), true),
+            (None, Some(id)) if config.verify(id, r_id, &anonymous.address) => {
+                let seen = diesel::update(room_listeners.find((r_id, id)))
+                    .set(last_seen.eq(now))
+                    .execute(conn)?;
+
+                // Anyone can ask for new ids, so an address only counts that many times
+                let counted = seen > 0 || {
+                    let tag = format!("{}.%", config.address_tag(&anonymous.address));
+
+                    let from_address: i64 = room_listeners
+                        .filter(room_id.eq(r_id))
+                        .filter(listener_id.like(tag))
+                        .filter(last_seen.ge(active_since()))
+                        .count()
+                        .get_result(conn)?;
+
+                    from_address < MAX_ANONYMOUS_PER_ADDRESS
+                };
+
+                (id.to_string(), counted)
+            }
+            (None, _) => (config.issue(r_id, &anonymous.address), false),
+        };
+
+        if counted {
+            diesel::insert_into(room_listeners)
+                .values(&NewRoomListener {
+                    room_id: r_id,
+                    listener_id: &id,
+                    user_id: user.map(|user| user.id),
+                    joined_on: now,
+                    last_seen: now,
+                })
+                .on_conflict((room_id, listener_id))
+                .do_update()
+                .set(last_seen.eq(now))
+                .execute(conn)?;
+        }
+
+        let listeners = room_listeners
+            .filter(room_id.eq(r_id))
+            .filter(last_seen.ge(active_since()))
+            .count()
+            .get_result(conn)?;
+
+        Ok(Heartbeat {
+            listener_id: id,
+            listeners,
+            counted,
+        })
+    }
+
+    /// Everyone who sent a heartbeat lately, the named listeners first
+    #[inline]
+    pub fn active(conn: &PgConnection, r_id: i64) -> Result<RoomListeners, ApiError> {
+        use diesel::prelude::*;
+        use schema::room_listeners::dsl::*;
+        use schema::users;
+
+        let result = room_listeners
+            .left_join(users::table)
+            .select((user_id, users::username.nullable(), joined_on))
+            .filter(room_id.eq(r_id))
+            .filter(last_seen.ge(active_since()))
+            .order((users::username.asc(), joined_on.asc()))
+            .load::<(Option<i64>, Option<String>, SystemTime)>(conn)?;
+
+        let listeners: Vec<Listener> = result
+            .into_iter()
+            .map(|(u_id, username, joined)| Listener {
+                user_id: u_id,
+                username,
+                joined_on: joined,
+            })
+            .collect();
+
+        Ok(RoomListeners {
+            room_id: r_id,
+            count: listeners.len(),
+            anonymous: listeners.iter().filter(|listener| listener.user_id.is_none()).count(),
+            listeners,
+        })
+    }
+
+    /// The amount of listeners in each of the rooms, in a single query.
+    /// Rooms without listeners are left out.
+    #[inline]
+    pub fn counts(conn: &PgConnection, room_ids: &[i64]) -> Result<HashMap<i64, i64>, ApiError> {
+        use diesel::dsl::sql;
+        use diesel::prelude::*;
+        use diesel::sql_types::BigInt;
+        use schema::room_listeners::dsl::*;
+
+        let result = room_listeners
+            .select((room_id, sql::<BigInt>("count(*)")))
+            .filter(room_id.eq_any(room_ids))
+            .filter(last_seen.ge(active_since()))
+            .group_by(room_id)
+            .load::<(i64, i64)>(conn)?;
+
+        Ok(result.into_iter().collect())
+    }
+
+    /// Removes the listeners that left, the counts already ignore them.
+    /// Returns the amount of removed listeners.
+    #[inline]
+    pub fn forget_stale(conn: &PgConnection) -> Result<usize, ApiError> {
+        use diesel::prelude::*;
+        use schema::room_listeners::dsl::*;
+
+        diesel::delete(room_listeners.filter(last_seen.lt(active_since())))
+            .execute(conn)
+            .map_err(Into::into)
+    }
+}
@@ -0,0 +1,281 @@
+use chrono::Utc;
+use dotenv::dotenv;
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use serde_json::{self, Map, Value};
+use std::cell::RefCell;
+use std::env;
+use std::sync::RwLock;
+use std::time::Instant;
+
+use auth::generate_token;
+use http::REQUEST_ID_HEADER;
+
+/// Logs a message together with structured fields, eg:
+/// `event!(Level::Info, "Started playing"; "room_id" => room.id, "video_id" => &video.video_id)`
+#[macro_export]
+macro_rules! event {
+    ($level:expr, $message:expr $(; $($key:expr => $value:expr),*)*) => {
+        $crate::logging::event(
+            $level,
+            module_path!(),
+            $message,
+            &[$($(($key, $crate::logging::field(&$value))),*)*],
+        )
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// One human readable line per message
+    Text,
+    /// One JSON object per line, for log pipelines
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Option<LogFormat> {
+        match value {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Configured through LOG_LEVEL and LOG_FORMAT in the environment or .env
+#[derive(Clone, Copy, Debug)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: LevelFilter::Info,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Panics on invalid values, like the settings in Rocket.toml
+    pub fn from_env() -> LogConfig {
+        dotenv().ok();
+
+        let mut config = LogConfig::default();
+
+        if let Ok(level) = env::var("LOG_LEVEL") {
+            config.level = level
+                .parse()
+                .unwrap_or_else(|_| panic!("LOG_LEVEL should be one of: off, error, warn, info, debug or trace."));
+        }
+
+        if let Ok(format) = env::var("LOG_FORMAT") {
+            config.format = LogFormat::parse(&format)
+                .unwrap_or_else(|| panic!("LOG_FORMAT should be either 'text' or 'json'."));
+        }
+
+        config
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: RwLock<LogConfig> = RwLock::new(LogConfig::default());
+}
+
+thread_local! {
+    // Rocket handles a request on a single thread, from the first fairing to the last
+    static REQUEST: RefCell<Option<(String, Instant)>> = RefCell::new(None);
+}
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        is_enabled(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Rocket logs its launch info as errors and uses "_" as target for indented lines
+        let (level, target) = match record.target() {
+            "launch" => (Level::Info, "rocket"),
+            "_" => (record.level(), "rocket"),
+            target => (record.level(), target),
+        };
+
+        write(level, target, &record.args().to_string(), &[]);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, this has to happen before Rocket is ignited or Rocket installs its own
+pub fn init_logging(config: LogConfig) {
+    if let Ok(mut current) = CONFIG.write() {
+        *current = config;
+    }
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(config.level);
+    }
+}
+
+// Dependencies only get to report problems, Rocket itself also gets to tell where it launched.
+// Every request is logged once by RequestLogger, so Rocket's own request lines are left out.
+fn is_enabled(level: Level, target: &str) -> bool {
+    let max_level = match CONFIG.read() {
+        Ok(config) => config.level,
+        Err(_) => LevelFilter::Info,
+    };
+
+    if target.starts_with("youkebox") || target == "launch" {
+        level <= max_level
+    } else {
+        level <= max_level && level <= Level::Warn
+    }
+}
+
+/// Use the `event!` macro instead
+pub fn event(level: Level, target: &str, message: &str, fields: &[(&str, Value)]) {
+    if is_enabled(level, target) {
+        write(level, target, message, fields);
+    }
+}
+
+#[doc(hidden)]
+pub fn field<T: ::serde::Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn write(level: Level, target: &str, message: &str, fields: &[(&str, Value)]) {
+    let format = match CONFIG.read() {
+        Ok(config) => config.format,
+        Err(_) => LogFormat::Text,
+    };
+
+    let timestamp = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let request_id = REQUEST.with(|request| request.borrow().as_ref().map(|r| r.0.clone()));
+
+    match format {
+        LogFormat::Text => {
+            let mut line = format!("{} {:<5} [{}] {}", timestamp, level, target, message);
+
+            for &(key, ref value) in fields {
+                match *value {
+                    Value::String(ref value) => line.push_str(&format!(" {}={:?}", key, value)),
+                    ref value => line.push_str(&format!(" {}={}", key, value)),
+                }
+            }
+
+            if let Some(request_id) = request_id {
+                line.push_str(&format!(" request_id={}", request_id));
+            }
+
+            println!("{}", line);
+        }
+        LogFormat::Json => {
+            let mut object = Map::new();
+            object.insert("timestamp".to_string(), Value::String(timestamp));
+            object.insert("level".to_string(), Value::String(level.to_string()));
+            object.insert("target".to_string(), Value::String(target.to_string()));
+            object.insert("message".to_string(), Value::String(message.to_string()));
+
+            for &(key, ref value) in fields {
+                object.insert(key.to_string(), value.clone());
+            }
+
+            if let Some(request_id) = request_id {
+                object.insert("request_id".to_string(), Value::String(request_id));
+            }
+
+            println!("{}", Value::Object(object));
+        }
+    }
+}
+
+/// Gives every request an id and logs it once it's answered.
+/// The id of the client or a proxy in front of us is kept when it sends a sane one.
+/// Everything logged while handling the request carries its id,
+/// and the id is sent back in the X-Request-Id header.
+pub struct RequestLogger;
+
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let request_id = match request.headers().get_one(REQUEST_ID_HEADER) {
+            Some(id) if is_valid_request_id(id) => id.to_string(),
+            _ => generate_token(16),
+        };
+
+        request.replace_header(Header::new(REQUEST_ID_HEADER, request_id.clone()));
+
+        REQUEST.with(|request| *request.borrow_mut() = Some((request_id, Instant::now())));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let started = REQUEST.with(|request| request.borrow_mut().take());
+
+        if let Some(request_id) = request.headers().get_one(REQUEST_ID_HEADER) {
+            response.set_header(Header::new(REQUEST_ID_HEADER, request_id.to_string()));
+        }
+
+        let duration_ms = started.map(|(_, started)| {
+            let elapsed = started.elapsed();
+            elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos()) / 1_000_000
+        });
+
+        let status = response.status().code;
+        let level = if status >= 500 { Level::Error } else { Level::Info };
+
+        event!(level, "Handled request";
+            "method" => request.method().as_str(),
+            "uri" => redact_uri(request.uri().as_str()),
+            "status" => status,
+            "duration_ms" => duration_ms,
+            "request_id" => request.headers().get_one(REQUEST_ID_HEADER)
+        );
+    }
+}
+
+/// The uri as it gets logged, with the value of the `invite` query parameter replaced,
+/// so invite links don't end up in the logs
+pub fn redact_uri(uri: &str) -> String {
+    let (path, query) = match uri.find('?') {
+        Some(index) => (&uri[..index], &uri[index + 1..]),
+        None => return uri.to_string(),
+    };
+
+    let query: Vec<&str> = query
+        .split('&')
+        .map(|pair| match pair.split('=').next() {
+            Some("invite") => "invite=[redacted]",
+            _ => pair,
+        })
+        .collect();
+
+    format!("{}?{}", path, query.join("&"))
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
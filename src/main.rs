@@ -8,22 +8,56 @@ extern crate rocket_contrib;
 extern crate rocket_cors;
 extern crate youkebox;
 
-use youkebox::init_pool;
+use youkebox::cli;
+use youkebox::favourite::YoutubePlaylists;
+use youkebox::storage::Storage;
+use youkebox::trending::{start_trending, TrendingConfig};
+use youkebox::{init_pool, Pool};
+use youkebox::janitor::{start_janitor, IdleAction, JanitorConfig};
+use youkebox::listener::ListenerConfig;
+use youkebox::logging::{init_logging, LogConfig, RequestLogger};
+use youkebox::metrics::RequestMetrics;
+use youkebox::migrations::init_migrations;
+use youkebox::names::{init_name_skeletons, NameRules};
+use youkebox::picture::init_pictures;
 use youkebox::player::init_playlist_listener;
 use youkebox::routes::*;
 
 use rocket::fairing::AdHoc;
 use rocket::http::Method;
 
+use std::{env, process};
+
 use youkebox::youtube::ApiKey;
 
 fn main() {
+    // Rocket only installs its own logger when there is none yet
+    init_logging(LogConfig::from_env());
+
+    // Subcommands like `youkebox migrate run` exit without starting the server
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        process::exit(cli::run(&args));
+    }
+
+    // Apply pending migrations when RUN_MIGRATIONS is set
+    init_migrations();
+
+    // Give names from before look-alike detection a skeleton
+    init_name_skeletons();
+
     // Start playing every playlist for every room
     init_playlist_listener();
 
     // Leave 'allowed_origins' empty because All is the default
     let options = rocket_cors::Cors {
-        allowed_methods: vec![Method::Get, Method::Post, Method::Delete]
+        allowed_methods: vec![
+            Method::Get,
+            Method::Post,
+            Method::Put,
+            Method::Patch,
+            Method::Delete,
+        ]
             .into_iter()
             .map(From::from)
             .collect(),
@@ -33,31 +67,74 @@ fn main() {
 
     rocket::ignite()
         .manage(init_pool())
-        .mount("/", routes![index])
+        .mount("/", routes![index, health, ready, show_metrics])
         .mount(
             "/api/v1",
             routes![
                 api_index,
                 get_playlist,
+                get_playlist_file,
+                get_room_stats,
                 search_video,
                 add_video,
                 skip_song_in_room,
                 show_rooms,
                 show_room,
+                show_room_by_slug,
                 search_rooms,
+                show_trending,
+                search_trending,
                 add_room,
+                update_room,
                 delete_room,
+                show_deleted_rooms,
+                restore_room,
+                export_room,
+                import_room,
                 set_room_picture,
-                get_room_picture
+                get_room_picture,
+                get_room_thumbnail,
+                get_room_settings,
+                update_room_settings,
+                get_room_invite,
+                reset_room_invite,
+                set_room_password,
+                unlock_room,
+                join_room,
+                show_room_members,
+                room_heartbeat,
+                show_room_listeners,
+                delete_room_member,
+                register,
+                login,
+                logout,
+                show_saved_playlists,
+                show_saved_playlist,
+                add_saved_playlist,
+                update_saved_playlist,
+                delete_saved_playlist,
+                queue_saved_playlist,
+                add_favourite,
+                show_favourites,
+                delete_favourite,
+                export_favourites
             ],
         )
-        .catch(errors![
+        .catch(catchers![
             bad_request,
+            unauthorized,
+            forbidden,
             not_found,
             conflict,
+            payload_too_large,
             unsupported_media_type,
-            internal_error
+            unprocessable_entity,
+            too_many_requests,
+            internal_error,
+            service_unavailable
         ])
+        .attach(RequestLogger)
+        .attach(RequestMetrics)
         .attach(options)
         .attach(AdHoc::on_attach(|rocket| {
             let youtube_api_key = rocket
@@ -72,5 +149,66 @@ fn main() {
 
             Ok(rocket.manage(ApiKey(youtube_api_key)))
         }))
+        .attach(AdHoc::on_attach(|rocket| {
+            let config = TrendingConfig::from_config(rocket.config());
+
+            start_trending(config);
+
+            Ok(rocket.manage(config))
+        }))
+        .attach(AdHoc::on_attach(|rocket| {
+            let playlists = YoutubePlaylists::from_config(rocket.config());
+
+            Ok(rocket.manage(playlists))
+        }))
+        .attach(AdHoc::on_attach(|rocket| {
+            let config = ListenerConfig::from_config(rocket.config());
+
+            Ok(rocket.manage(config))
+        }))
+        .attach(AdHoc::on_attach(|rocket| {
+            let rules = NameRules::from_config(rocket.config());
+
+            Ok(rocket.manage(rules))
+        }))
+        .attach(AdHoc::on_attach(|rocket| {
+            let storage = Storage::from_config(
+                rocket.config(),
+                rocket.state::<Pool>().expect("No database pool."),
+            );
+
+            // Give pictures from before the picture pipeline thumbnails and a content type
+            init_pictures(&storage);
+
+            Ok(rocket.manage(storage))
+        }))
+        .attach(AdHoc::on_attach(|rocket| {
+            let config = {
+                let config = rocket.config();
+
+                // Idle rooms are kept forever unless ROOM_IDLE_DAYS is set
+                let idle_days = config.get_int("ROOM_IDLE_DAYS").unwrap_or(0);
+
+                let action = IdleAction::parse(config.get_str("ROOM_IDLE_ACTION").unwrap_or("archive"))
+                    .expect("ROOM_IDLE_ACTION should be either 'archive' or 'delete'.");
+
+                let retention_days = config.get_int("ROOM_DELETE_RETENTION_DAYS").unwrap_or(30);
+
+                JanitorConfig {
+                    idle_days: idle_days.max(0) as u64,
+                    action,
+                    retention_days: retention_days.max(0) as u64,
+                }
+            };
+
+            let storage = rocket
+                .state::<Storage>()
+                .expect("No picture storage.")
+                .clone();
+
+            start_janitor(config, storage);
+
+            Ok(rocket.manage(config))
+        }))
         .launch();
 }
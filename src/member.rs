@@ -0,0 +1,99 @@
+use diesel;
+use diesel::pg::PgConnection;
+use rocket::http::Status;
+use rocket::response::Failure;
+use std::time::SystemTime;
+
+use schema::room_members;
+
+#[derive(Serialize, Queryable)]
+pub struct RoomMember {
+    pub room_id: i64,
+    pub user_id: i64,
+    pub joined_on: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "room_members"]
+pub struct NewRoomMember {
+    pub room_id: i64,
+    pub user_id: i64,
+}
+
+impl RoomMember {
+    // Adding someone who already is a member is a no-op
+    #[inline]
+    pub fn add(conn: &PgConnection, r_id: i64, u_id: i64) -> Result<(), Failure> {
+        use diesel::prelude::*;
+
+        let result = diesel::insert_into(room_members::table)
+            .values(&NewRoomMember {
+                room_id: r_id,
+                user_id: u_id,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn);
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error while adding a room member: {}", e);
+                Err(Failure(Status::InternalServerError))
+            }
+        }
+    }
+
+    #[inline]
+    pub fn remove(conn: &PgConnection, r_id: i64, u_id: i64) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::room_members::dsl::*;
+
+        let result = diesel::delete(
+            room_members
+                .filter(room_id.eq(r_id))
+                .filter(user_id.eq(u_id)),
+        )
+        .execute(conn);
+
+        match result {
+            Ok(0) => Err(Failure(Status::NotFound)),
+            Ok(_) => Ok(()),
+            Err(_) => Err(Failure(Status::InternalServerError)),
+        }
+    }
+
+    #[inline]
+    pub fn exists(conn: &PgConnection, r_id: i64, u_id: i64) -> bool {
+        use diesel::dsl::exists;
+        use diesel::prelude::*;
+        use schema::room_members::dsl::*;
+
+        diesel::select(exists(
+            room_members
+                .filter(room_id.eq(r_id))
+                .filter(user_id.eq(u_id)),
+        ))
+        .get_result(conn)
+        .unwrap_or(false)
+    }
+
+    // Return every member of a room
+    #[inline]
+    pub fn all(conn: &PgConnection, r_id: i64) -> Result<Vec<RoomMember>, Failure> {
+        use diesel::prelude::*;
+        use schema::room_members::dsl::*;
+
+        let result = room_members
+            .filter(room_id.eq(r_id))
+            .order(joined_on.asc())
+            .load::<RoomMember>(conn);
+
+        match result {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                error!("Error while fetching the room members: {}", e);
+                Err(Failure(Status::InternalServerError))
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use player::active_rooms;
+use Pool;
+
+// Request latencies in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+// Picture uploads in bytes, up to picture::MAX_UPLOAD_SIZE
+const UPLOAD_BUCKETS: &[f64] = &[
+    16_384.0,
+    65_536.0,
+    262_144.0,
+    1_048_576.0,
+    2_097_152.0,
+    4_194_304.0,
+    8_388_608.0,
+];
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+thread_local! {
+    // Rocket handles a request on a single thread, from the first fairing to the last
+    static REQUEST_STARTED: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// The calls we make to the YouTube API, together with what they cost of the daily quota
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum YoutubeCall {
+    Search,
+    Videos,
+    /// Creating a playlist or adding a song to one, on behalf of a user
+    Playlists,
+}
+
+impl YoutubeCall {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            YoutubeCall::Search => "search",
+            YoutubeCall::Videos => "videos",
+            YoutubeCall::Playlists => "playlists",
+        }
+    }
+
+    /// Estimated quota units, see https://developers.google.com/youtube/v3/determine_quota_cost
+    pub fn quota_units(&self) -> u64 {
+        match *self {
+            YoutubeCall::Search => 100,
+            YoutubeCall::Videos => 1,
+            YoutubeCall::Playlists => 50,
+        }
+    }
+}
+
+pub struct Histogram {
+    buckets: &'static [f64],
+    // Cumulative, like Prometheus expects them
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(buckets: &'static [f64]) -> Histogram {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter()) {
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bucket, count);
+        }
+
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count);
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/// Everything we count, rendered in the Prometheus text format
+pub struct Metrics {
+    /// (method, route, status)
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// (method, route)
+    latencies: BTreeMap<(String, String), Histogram>,
+    /// Per room id
+    videos_played: BTreeMap<i64, u64>,
+    videos_skipped: BTreeMap<i64, u64>,
+    youtube_calls: BTreeMap<YoutubeCall, u64>,
+    youtube_failures: BTreeMap<YoutubeCall, u64>,
+    picture_uploads: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics {
+            requests: BTreeMap::new(),
+            latencies: BTreeMap::new(),
+            videos_played: BTreeMap::new(),
+            videos_skipped: BTreeMap::new(),
+            youtube_calls: BTreeMap::new(),
+            youtube_failures: BTreeMap::new(),
+            picture_uploads: Histogram::new(UPLOAD_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn observe_request(&mut self, method: &str, route: &str, status: u16, duration: Duration) {
+        *self
+            .requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_insert(0) += 1;
+
+        self.latencies
+            .entry((method.to_string(), route.to_string()))
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(seconds(duration));
+    }
+
+    pub fn video_played(&mut self, room_id: i64) {
+        *self.videos_played.entry(room_id).or_insert(0) += 1;
+    }
+
+    pub fn video_skipped(&mut self, room_id: i64) {
+        *self.videos_skipped.entry(room_id).or_insert(0) += 1;
+    }
+
+    pub fn youtube_call(&mut self, call: YoutubeCall, success: bool) {
+        *self.youtube_calls.entry(call).or_insert(0) += 1;
+
+        if !success {
+            *self.youtube_failures.entry(call).or_insert(0) += 1;
+        }
+    }
+
+    pub fn picture_uploaded(&mut self, size: usize) {
+        self.picture_uploads.observe(size as f64);
+    }
+
+    pub fn render(&self, out: &mut String) {
+        header(out, "youkebox_http_requests_total", "counter", "Handled requests per route and status");
+        for (&(ref method, ref route, status), count) in &self.requests {
+            let _ = writeln!(
+                out,
+                "youkebox_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method,
+                escape(route),
+                status,
+                count
+            );
+        }
+
+        header(out, "youkebox_http_request_duration_seconds", "histogram", "Time spent handling requests per route");
+        for (&(ref method, ref route), histogram) in &self.latencies {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape(route));
+            histogram.render(out, "youkebox_http_request_duration_seconds", &labels);
+        }
+
+        header(out, "youkebox_videos_played_total", "counter", "Videos played until the end per room");
+        for (room_id, count) in &self.videos_played {
+            let _ = writeln!(out, "youkebox_videos_played_total{{room_id=\"{}\"}} {}", room_id, count);
+        }
+
+        header(out, "youkebox_videos_skipped_total", "counter", "Skipped videos per room");
+        for (room_id, count) in &self.videos_skipped {
+            let _ = writeln!(out, "youkebox_videos_skipped_total{{room_id=\"{}\"}} {}", room_id, count);
+        }
+
+        header(out, "youkebox_youtube_calls_total", "counter", "Calls to the YouTube API");
+        for (call, count) in &self.youtube_calls {
+            let _ = writeln!(out, "youkebox_youtube_calls_total{{call=\"{}\"}} {}", call.as_str(), count);
+        }
+
+        header(out, "youkebox_youtube_failures_total", "counter", "Failed calls to the YouTube API");
+        for (call, count) in &self.youtube_failures {
+            let _ = writeln!(out, "youkebox_youtube_failures_total{{call=\"{}\"}} {}", call.as_str(), count);
+        }
+
+        header(out, "youkebox_youtube_quota_units_total", "counter", "Estimated YouTube API quota units used");
+        for (call, count) in &self.youtube_calls {
+            let _ = writeln!(
+                out,
+                "youkebox_youtube_quota_units_total{{call=\"{}\"}} {}",
+                call.as_str(),
+                count * call.quota_units()
+            );
+        }
+
+        header(out, "youkebox_picture_upload_bytes", "histogram", "Size of the uploaded room pictures");
+        self.picture_uploads.render(out, "youkebox_picture_upload_bytes", "");
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Label values can't contain unescaped backslashes, quotes or newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Updates the global metrics, a poisoned lock only means losing some counts
+pub fn record<F: FnOnce(&mut Metrics)>(update: F) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        update(&mut metrics);
+    }
+}
+
+/// Every metric together with the gauges that are read when scraped
+pub fn render(pool: &Pool) -> String {
+    let mut out = String::new();
+
+    if let Ok(metrics) = METRICS.lock() {
+        metrics.render(&mut out);
+    }
+
+    let state = pool.state();
+
+    header(&mut out, "youkebox_player_rooms", "gauge", "Rooms with a running player");
+    let _ = writeln!(out, "youkebox_player_rooms {}", active_rooms());
+
+    header(&mut out, "youkebox_db_pool_connections", "gauge", "Open database connections");
+    let _ = writeln!(out, "youkebox_db_pool_connections {}", state.connections);
+
+    header(&mut out, "youkebox_db_pool_idle_connections", "gauge", "Idle database connections");
+    let _ = writeln!(out, "youkebox_db_pool_idle_connections {}", state.idle_connections);
+
+    header(&mut out, "youkebox_db_pool_max_connections", "gauge", "Maximum database connections");
+    let _ = writeln!(out, "youkebox_db_pool_max_connections {}", pool.max_size());
+
+    out
+}
+
+/// Counts every request and how long it took, per route
+pub struct RequestMetrics;
+
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, _: &mut Request, _: &Data) {
+        REQUEST_STARTED.with(|started| started.set(Some(Instant::now())));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let started = match REQUEST_STARTED.with(|started| started.replace(None)) {
+            Some(started) => started,
+            None => return,
+        };
+
+        // Label with the route instead of the path, so ids don't explode the amount of series
+        let route = match request.route() {
+            Some(route) => route.uri.path().to_string(),
+            None => "unmatched".to_string(),
+        };
+
+        record(|metrics| {
+            metrics.observe_request(
+                request.method().as_str(),
+                &route,
+                response.status().code,
+                started.elapsed(),
+            )
+        });
+    }
+}
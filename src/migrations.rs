@@ -0,0 +1,118 @@
+use diesel::migration::RunMigrationsError;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Bool, VarChar};
+use diesel_migrations;
+use dotenv::dotenv;
+use log::Level;
+use std::env;
+use std::io::{self, Write};
+
+use establish_connection;
+
+// Compiles every migration in migrations/ into the binary, so deployments don't need the diesel CLI
+embed_migrations!();
+
+/// A migration from `migrations/`, as listed by build.rs
+pub struct MigrationInfo {
+    pub version: &'static str,
+    pub name: &'static str,
+}
+
+// Every directory in migrations/, oldest first
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+#[derive(Serialize, Debug)]
+pub struct MigrationStatus {
+    pub version: &'static str,
+    pub name: &'static str,
+    pub applied: bool,
+}
+
+#[derive(QueryableByName)]
+struct MigrationsTable {
+    #[sql_type = "Bool"]
+    exists: bool,
+}
+
+#[derive(QueryableByName)]
+struct AppliedMigration {
+    #[sql_type = "VarChar"]
+    version: String,
+}
+
+// Unlike MigrationConnection::previously_run_migration_versions this doesn't need
+// the migrations table to exist, so asking for the status never changes the database
+fn applied_versions(conn: &PgConnection) -> QueryResult<Vec<String>> {
+    let table = sql_query(
+        "SELECT EXISTS (SELECT 1 FROM information_schema.tables \
+         WHERE table_name = '__diesel_schema_migrations') AS exists",
+    ).get_result::<MigrationsTable>(conn)?;
+
+    if !table.exists {
+        return Ok(Vec::new());
+    }
+
+    sql_query("SELECT version FROM __diesel_schema_migrations")
+        .load::<AppliedMigration>(conn)
+        .map(|applied| applied.into_iter().map(|migration| migration.version).collect())
+}
+
+/// Every migration and whether it was applied
+pub fn status(conn: &PgConnection) -> QueryResult<Vec<MigrationStatus>> {
+    let applied = applied_versions(conn)?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            name: migration.name,
+            applied: applied.iter().any(|version| version == migration.version),
+        })
+        .collect())
+}
+
+pub fn pending(conn: &PgConnection) -> QueryResult<Vec<&'static MigrationInfo>> {
+    let applied = applied_versions(conn)?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.iter().any(|version| version == migration.version))
+        .collect())
+}
+
+/// Applies every pending migration with diesel's embedded runner, each one in its own transaction
+pub fn run_pending(conn: &PgConnection, output: &mut Write) -> Result<(), RunMigrationsError> {
+    embedded_migrations::run_with_output(conn, output)
+}
+
+/// Reverts the migration that was applied last and returns its version.
+/// Only the up.sql of a migration is embedded, so this reads down.sql from migrations/.
+pub fn revert_latest(conn: &PgConnection) -> Result<String, RunMigrationsError> {
+    let directory = diesel_migrations::find_migrations_directory()?;
+    diesel_migrations::revert_latest_migration_in_directory(conn, &directory)
+}
+
+/// Applies the pending migrations when RUN_MIGRATIONS=true is set in the environment or .env,
+/// this has to happen before anything else touches the database
+pub fn init_migrations() {
+    dotenv().ok();
+
+    match env::var("RUN_MIGRATIONS") {
+        Ok(ref value) if value == "true" => {}
+        Ok(ref value) if value == "false" => return,
+        Ok(_) => panic!("RUN_MIGRATIONS should be either 'true' or 'false'."),
+        Err(_) => return,
+    }
+
+    let conn = establish_connection();
+
+    let pending = pending(&conn).expect("Unable to load the applied migrations");
+
+    run_pending(&conn, &mut io::sink()).unwrap_or_else(|e| panic!("Unable to run the migrations: {}", e));
+
+    for migration in pending {
+        event!(Level::Info, "Applied migration"; "version" => migration.version, "name" => migration.name);
+    }
+}
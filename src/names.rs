@@ -0,0 +1,266 @@
+use diesel;
+use diesel::pg::PgConnection;
+use rocket::config::Config;
+use unicode_normalization::UnicodeNormalization;
+use unicode_skeleton::UnicodeSkeleton;
+
+use establish_connection;
+
+/// The kinds of characters a room or user name can be made of
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CharClass {
+    Letters,
+    Digits,
+    Spaces,
+    /// ASCII punctuation, eg: - _ . ' & !
+    Punctuation,
+    /// Emoji and every other visible character
+    Symbols,
+}
+
+impl CharClass {
+    pub const VALUES: &'static [&'static str] =
+        &["letters", "digits", "spaces", "punctuation", "symbols"];
+
+    pub fn parse(value: &str) -> Option<CharClass> {
+        match value {
+            "letters" => Some(CharClass::Letters),
+            "digits" => Some(CharClass::Digits),
+            "spaces" => Some(CharClass::Spaces),
+            "punctuation" => Some(CharClass::Punctuation),
+            "symbols" => Some(CharClass::Symbols),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CharClass::Letters => "letters",
+            CharClass::Digits => "digits",
+            CharClass::Spaces => "spaces",
+            CharClass::Punctuation => "punctuation",
+            CharClass::Symbols => "symbols",
+        }
+    }
+
+    fn of(c: char) -> CharClass {
+        if c.is_alphabetic() {
+            CharClass::Letters
+        } else if c.is_numeric() {
+            CharClass::Digits
+        } else if c == ' ' {
+            CharClass::Spaces
+        } else if c.is_ascii_punctuation() {
+            CharClass::Punctuation
+        } else {
+            CharClass::Symbols
+        }
+    }
+}
+
+/// Configured through NAME_MIN_LENGTH, NAME_MAX_LENGTH and NAME_CHARSET in Rocket.toml
+#[derive(Clone, Debug)]
+pub struct NameRules {
+    /// Lengths are counted in characters after normalization
+    pub min_length: usize,
+    pub max_length: usize,
+    pub charset: Vec<CharClass>,
+}
+
+impl Default for NameRules {
+    fn default() -> NameRules {
+        NameRules {
+            min_length: 3,
+            max_length: 20,
+            charset: vec![
+                CharClass::Letters,
+                CharClass::Digits,
+                CharClass::Spaces,
+                CharClass::Punctuation,
+                CharClass::Symbols,
+            ],
+        }
+    }
+}
+
+impl NameRules {
+    /// Reads the rules from Rocket.toml, settings that are left out keep their default.
+    /// Panics on an invalid NAME_CHARSET, like the other settings in Rocket.toml
+    pub fn from_config(config: &Config) -> NameRules {
+        let defaults = NameRules::default();
+
+        let charset = match config.get_str("NAME_CHARSET") {
+            Ok(charset) => NameRules::parse_charset(charset)
+                .unwrap_or_else(|e| panic!("Invalid NAME_CHARSET: {}", e)),
+            Err(_) => defaults.charset,
+        };
+
+        NameRules {
+            min_length: config
+                .get_int("NAME_MIN_LENGTH")
+                .map(|length| length.max(1) as usize)
+                .unwrap_or(defaults.min_length),
+            max_length: config
+                .get_int("NAME_MAX_LENGTH")
+                .map(|length| length.max(1) as usize)
+                .unwrap_or(defaults.max_length),
+            charset,
+        }
+    }
+
+    /// Parses a comma separated list of character classes, eg: "letters,digits,spaces"
+    pub fn parse_charset(value: &str) -> Result<Vec<CharClass>, String> {
+        value
+            .split(',')
+            .map(|class| class.trim())
+            .filter(|class| !class.is_empty())
+            .map(|class| {
+                CharClass::parse(class).ok_or_else(|| {
+                    format!(
+                        "Unknown character class '{}', expected one of: {}.",
+                        class,
+                        CharClass::VALUES.join(", ")
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Normalizes a name and checks it against the rules.
+    /// Returns the normalized name or a message that can be shown to the user.
+    pub fn check(&self, raw: &str) -> Result<String, String> {
+        let name = normalize(raw);
+
+        if name.is_empty() {
+            return Err("Can't be empty.".to_string());
+        }
+
+        if let Some(c) = name.chars().find(|&c| is_invisible(c)) {
+            return Err(format!(
+                "Can't contain invisible or control characters (U+{:04X}).",
+                c as u32
+            ));
+        }
+
+        for c in name.chars() {
+            // Joiners and variation selectors only glue emoji together
+            if is_emoji_glue(c) && self.charset.contains(&CharClass::Symbols) {
+                continue;
+            }
+
+            if !self.charset.contains(&CharClass::of(c)) {
+                return Err(format!(
+                    "Can't contain '{}', only {} are allowed.",
+                    c,
+                    self.describe_charset()
+                ));
+            }
+        }
+
+        let length = name.chars().count();
+
+        if length < self.min_length || length > self.max_length {
+            return Err(format!(
+                "Must be between {} and {} characters long.",
+                self.min_length, self.max_length
+            ));
+        }
+
+        Ok(name)
+    }
+
+    fn describe_charset(&self) -> String {
+        let classes: Vec<&str> = self.charset.iter().map(|class| class.as_str()).collect();
+
+        match classes.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+            Some((last, _)) => last.to_string(),
+            None => "no characters".to_string(),
+        }
+    }
+}
+
+/// NFKC normalizes a name, trims it and collapses every run of whitespace into a single space.
+/// eg: "Ｆｒｉｄａｙ\u{a0} Night " becomes "Friday Night".
+pub fn normalize(raw: &str) -> String {
+    let name: String = raw.nfkc().collect();
+
+    name.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Case insensitive UTS #39 skeleton of a name.
+/// Names with the same skeleton look alike,
+/// eg: "room" and "r00m" or "Metal" and "Mеtal" (with a Cyrillic "е").
+pub fn skeleton(name: &str) -> String {
+    normalize(name)
+        .to_lowercase()
+        .skeleton_chars()
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn is_emoji_glue(c: char) -> bool {
+    match c {
+        '\u{200D}' | '\u{FE00}'..='\u{FE0F}' | '\u{E0020}'..='\u{E007F}' => true,
+        _ => false,
+    }
+}
+
+fn is_invisible(c: char) -> bool {
+    if c.is_control() {
+        return true;
+    }
+
+    match c {
+        '\u{00AD}' | '\u{034F}' | '\u{061C}' | '\u{115F}' | '\u{1160}' | '\u{17B4}'
+        | '\u{17B5}' | '\u{180E}' | '\u{200B}' | '\u{200C}' | '\u{200E}' | '\u{200F}'
+        | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{206F}'
+        | '\u{3164}' | '\u{FEFF}' | '\u{FFA0}' => true,
+        _ => false,
+    }
+}
+
+// Rooms and users created before skeletons were stored don't have one yet
+fn backfill_skeletons(conn: &PgConnection) {
+    use diesel::prelude::*;
+    use schema::{rooms, users};
+
+    let legacy_rooms = rooms::table
+        .select((rooms::id, rooms::name))
+        .filter(rooms::name_skeleton.is_null())
+        .load::<(i64, String)>(conn)
+        .unwrap_or_default();
+
+    for (room_id, name) in legacy_rooms {
+        let result = diesel::update(rooms::table.filter(rooms::id.eq(room_id)))
+            .set(rooms::name_skeleton.eq(skeleton(&name)))
+            .execute(conn);
+
+        if let Err(e) = result {
+            warn!("Unable to store the name skeleton of room [{}]: {}", &name, e);
+        }
+    }
+
+    let legacy_users = users::table
+        .select((users::id, users::username))
+        .filter(users::username_skeleton.is_null())
+        .load::<(i64, String)>(conn)
+        .unwrap_or_default();
+
+    for (user_id, name) in legacy_users {
+        let result = diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set(users::username_skeleton.eq(skeleton(&name)))
+            .execute(conn);
+
+        if let Err(e) = result {
+            warn!("Unable to store the name skeleton of user [{}]: {}", &name, e);
+        }
+    }
+}
+
+/// Stores the skeletons of names that don't have one yet.
+/// Legacy names that look alike keep working, but are reported and left without a skeleton.
+pub fn init_name_skeletons() {
+    let conn: PgConnection = establish_connection();
+    backfill_skeletons(&conn);
+}
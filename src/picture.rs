@@ -0,0 +1,254 @@
+use image::{self, bmp, gif, hdr, ico, jpeg, png, pnm, tiff, webp};
+use image::{DynamicImage, FilterType, GenericImage, ImageDecoder, ImageFormat, ImageResult};
+use rocket::http::hyper::header::{CacheControl, CacheDirective, ETag, EntityTag};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Failure, Responder, Response};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{Cursor, Read};
+
+use diesel::pg::PgConnection;
+use establish_connection;
+use room::Room;
+use storage::{PictureStorage, Storage};
+use PICTURES_DIR;
+
+/// Pictures larger than this are scaled down to fit a square of this size
+pub const PICTURE_SIZE: u32 = 512;
+/// Every picture also gets a thumbnail that fits each of these squares
+pub const THUMBNAIL_SIZES: &[u32] = &[64, 128, 256];
+/// Every picture is re-encoded to this format
+pub const PICTURE_TYPE: &str = "image/png";
+/// Uploads larger than this many bytes are rejected with a 413
+pub const MAX_UPLOAD_SIZE: u64 = 8 * 1024 * 1024;
+/// Pictures with more pixels than this (5000x5000) are rejected with a 422, before they're decoded
+pub const MAX_PICTURE_PIXELS: u64 = 25_000_000;
+
+// Browsers may reuse a picture for an hour before checking its ETag
+const MAX_AGE: u32 = 60 * 60;
+
+/// An uploaded picture, re-encoded and scaled down, together with its thumbnails
+pub struct ProcessedPicture {
+    pub picture: Vec<u8>,
+    pub thumbnails: Vec<(u32, Vec<u8>)>,
+}
+
+/// A stored picture, ready to be sent to a client
+pub struct Picture {
+    pub data: Vec<u8>,
+    pub content_type: ContentType,
+    /// Only public pictures may be kept by shared caches
+    pub public: bool,
+}
+
+/// Reads an upload of at most MAX_UPLOAD_SIZE bytes.
+/// Larger uploads are rejected with a 413 as soon as they cross the limit.
+pub fn read_upload<R: Read>(upload: R) -> Result<Vec<u8>, Failure> {
+    let mut data = Vec::new();
+
+    // Read a single byte past the limit to tell an upload of exactly the limit from a larger one
+    match upload.take(MAX_UPLOAD_SIZE + 1).read_to_end(&mut data) {
+        Ok(_) if data.len() as u64 > MAX_UPLOAD_SIZE => Err(Failure(Status::PayloadTooLarge)),
+        Ok(_) => Ok(data),
+        Err(e) => {
+            error!("Failed to read the uploaded picture: {}", e);
+            Err(Failure(Status::InternalServerError))
+        }
+    }
+}
+
+/// Decodes an upload, scales it down to PICTURE_SIZE and renders the thumbnails.
+/// Anything that isn't a supported image is rejected with a 415.
+pub fn process(upload: &[u8]) -> Result<ProcessedPicture, Failure> {
+    // A small upload can decode to gigabytes of pixels, so the header is checked first
+    match dimensions(upload) {
+        Ok((width, height)) if u64::from(width) * u64::from(height) > MAX_PICTURE_PIXELS => {
+            return Err(Failure(Status::UnprocessableEntity))
+        }
+        Ok(_) => {}
+        Err(_) => return Err(Failure(Status::UnsupportedMediaType)),
+    }
+
+    let picture = match image::load_from_memory(upload) {
+        Ok(picture) => picture,
+        Err(_) => return Err(Failure(Status::UnsupportedMediaType)),
+    };
+
+    let picture = fit(&picture, PICTURE_SIZE, FilterType::Lanczos3);
+
+    let mut thumbnails = Vec::with_capacity(THUMBNAIL_SIZES.len());
+
+    for &size in THUMBNAIL_SIZES {
+        thumbnails.push((size, encode(&fit(&picture, size, FilterType::Triangle))?));
+    }
+
+    Ok(ProcessedPicture {
+        picture: encode(&picture)?,
+        thumbnails,
+    })
+}
+
+/// Where the thumbnail of a picture of the given size is stored, eg: 42.png -> 42_64.png
+pub fn thumbnail_path(picture_path: &str, size: u32) -> String {
+    match picture_path.rfind('.') {
+        Some(dot) => format!("{}_{}{}", &picture_path[..dot], size, &picture_path[dot..]),
+        None => format!("{}_{}", picture_path, size),
+    }
+}
+
+/// Where the picture of a room is stored, eg: 42.png
+pub fn picture_path(room_id: i64) -> String {
+    format!("{}.png", room_id)
+}
+
+/// Stores a processed picture and its thumbnails.
+/// Returns the key of the picture, the thumbnails are stored under `thumbnail_path(key, size)`.
+pub fn save(
+    storage: &PictureStorage,
+    room_id: i64,
+    processed: &ProcessedPicture,
+) -> Result<String, Failure> {
+    let picture_path = picture_path(room_id);
+
+    storage.put(&picture_path, &processed.picture)?;
+
+    for &(size, ref thumbnail) in &processed.thumbnails {
+        storage.put(&thumbnail_path(&picture_path, size), thumbnail)?;
+    }
+
+    Ok(picture_path)
+}
+
+/// Removes a picture and its thumbnails
+pub fn remove(storage: &PictureStorage, picture_path: &str) -> Result<(), Failure> {
+    storage.delete(picture_path)?;
+
+    for &size in THUMBNAIL_SIZES {
+        storage.delete(&thumbnail_path(picture_path, size))?;
+    }
+
+    Ok(())
+}
+
+// Pictures used to be stored in PICTURES_DIR as they were uploaded, under the id of their room.
+// Run them through the pipeline so they get thumbnails and a known type.
+fn convert_legacy_pictures(conn: &PgConnection, storage: &PictureStorage) {
+    let entries = match fs::read_dir(*PICTURES_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let room_id = match entry.file_name().to_str().and_then(|f| f.parse::<i64>().ok()) {
+            Some(room_id) => room_id,
+            None => continue,
+        };
+
+        let result = fs::read(entry.path())
+            .map_err(|_| Failure(Status::InternalServerError))
+            .and_then(|upload| process(&upload))
+            .and_then(|processed| save(storage, room_id, &processed))
+            .and_then(|picture_path| {
+                Room::set_picture(conn, room_id, Some((&picture_path, PICTURE_TYPE)))
+            });
+
+        match result {
+            Ok(_) => {
+                let _res = fs::remove_file(entry.path());
+            }
+            Err(e) => error!("Unable to convert the picture of room {}: {:?}", room_id, e),
+        }
+    }
+}
+
+/// Converts the pictures that were stored before they were processed and moves them to the storage
+pub fn init_pictures(storage: &Storage) {
+    let conn: PgConnection = establish_connection();
+    convert_legacy_pictures(&conn, &*storage.0);
+}
+
+// The width and height an upload states in its header, without decoding the pixels
+fn dimensions(upload: &[u8]) -> ImageResult<(u32, u32)> {
+    let reader = Cursor::new(upload);
+
+    match image::guess_format(upload)? {
+        ImageFormat::PNG => png::PNGDecoder::new(reader).dimensions(),
+        ImageFormat::JPEG => jpeg::JPEGDecoder::new(reader).dimensions(),
+        ImageFormat::GIF => gif::Decoder::new(reader).dimensions(),
+        ImageFormat::WEBP => webp::WebpDecoder::new(reader).dimensions(),
+        ImageFormat::TIFF => tiff::TIFFDecoder::new(reader)?.dimensions(),
+        ImageFormat::BMP => bmp::BMPDecoder::new(reader).dimensions(),
+        ImageFormat::ICO => ico::ICODecoder::new(reader)?.dimensions(),
+        ImageFormat::HDR => hdr::HDRAdapter::new(reader)?.dimensions(),
+        // PPM and PNM, guess_format doesn't recognize TGA
+        _ => pnm::PNMDecoder::new(reader)?.dimensions(),
+    }
+}
+
+// Scale a picture down so it fits in a size x size square, smaller pictures are left alone
+fn fit(picture: &DynamicImage, size: u32, filter: FilterType) -> DynamicImage {
+    if picture.width() <= size && picture.height() <= size {
+        return picture.clone();
+    }
+
+    picture.resize(size, size, filter)
+}
+
+fn encode(picture: &DynamicImage) -> Result<Vec<u8>, Failure> {
+    let mut data = Vec::new();
+
+    match picture.save(&mut data, ImageFormat::PNG) {
+        Ok(_) => Ok(data),
+        Err(e) => {
+            error!("Failed to encode the picture: {}", e);
+            Err(Failure(Status::InternalServerError))
+        }
+    }
+}
+
+impl Picture {
+    pub fn etag(&self) -> EntityTag {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&self.data);
+
+        EntityTag::strong(format!("{:016x}", hasher.finish()))
+    }
+}
+
+impl<'r> Responder<'r> for Picture {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        let etag = self.etag();
+
+        let visibility = if self.public {
+            CacheDirective::Public
+        } else {
+            CacheDirective::Private
+        };
+        let cache_control = CacheControl(vec![visibility, CacheDirective::MaxAge(MAX_AGE)]);
+
+        // The client already has this exact picture
+        let not_modified = req
+            .headers()
+            .get("If-None-Match")
+            .flat_map(|header| header.split(','))
+            .filter_map(|tag| match tag.trim() {
+                "*" => Some(etag.clone()),
+                tag => tag.parse::<EntityTag>().ok(),
+            })
+            .any(|tag| tag.weak_eq(&etag));
+
+        let mut response = Response::build();
+        response.header(ETag(etag)).header(cache_control);
+
+        if not_modified {
+            return response.status(Status::NotModified).ok();
+        }
+
+        response
+            .header(self.content_type)
+            .sized_body(Cursor::new(self.data))
+            .ok()
+    }
+}
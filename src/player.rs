@@ -1,22 +1,36 @@
 use diesel::prelude::*;
 use diesel::pg::PgConnection;
+use log::Level;
 use video::Video;
 use room::Room;
 use std::{thread, time};
 use schema;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 use std::collections::HashMap;
 use std::sync::Mutex;
 
 use establish_connection;
+use metrics;
+
+/// How often the players are compared with the rooms in the database, in seconds
+pub const PLAYER_SYNC_INTERVAL: u64 = 30;
 
 lazy_static! {
     static ref PLAYLIST_THREADS: Mutex<HashMap<i64, VideoStatus>> = Mutex::new(HashMap::new());
+    static ref SUPERVISOR: Mutex<Supervisor> = Mutex::new(Supervisor::default());
+}
+
+// When the players were last synced with the database and when a player thread last panicked
+#[derive(Default)]
+struct Supervisor {
+    last_sync: Option<Instant>,
+    last_panic: Option<Instant>,
 }
 
 enum VideoStatus {
     Play,
     Skip,
+    Stop,
 }
 
 /// Fetches the current video from the playlist and waits for the duration of the video
@@ -31,16 +45,32 @@ pub fn play_current_video(conn: &PgConnection, room: &Room) -> bool {
 
     match video {
         Ok(video) => {
-            let video_duration = time::Duration::from_secs(duration_to_seconds(&video.duration));
+            // Songs without a valid duration are marked as played right away
+            let video_duration = match duration_to_seconds(&video.duration) {
+                Some(seconds) => time::Duration::from_secs(seconds),
+                None => {
+                    event!(Level::Warn, "Invalid video duration";
+                        "room_id" => room.id,
+                        "video_id" => video.id,
+                        "duration" => &video.duration
+                    );
+                    time::Duration::from_secs(0)
+                }
+            };
 
             super::diesel::update(&video)
                 .set(started_on.eq(SystemTime::now()))
                 .execute(conn)
                 .expect("Unable to start playing the current video.");
 
-            println!(
-                "Start playing: [{}] With ID: [{}] and duration: [{}] in room: [{}].",
-                &video.title, &video.id, &video.duration, &room.name
+            Room::touch(conn, room.id);
+
+            event!(Level::Info, "Started playing video";
+                "room_id" => room.id,
+                "video_id" => video.id,
+                "youtube_id" => &video.video_id,
+                "title" => &video.title,
+                "duration" => video_duration.as_secs()
             );
 
             PLAYLIST_THREADS
@@ -50,15 +80,23 @@ pub fn play_current_video(conn: &PgConnection, room: &Room) -> bool {
 
             let now = SystemTime::now();
             let mut playing: bool = true;
+            let mut skipped = false;
 
             // Continue playing this song while playing is true
             // Playing will be set to false if either the timer has run out
             // Or when someone skips the song by setting the PLAYLIST_THREADS[ROOM_NAME] to something other than "play"
             while playing {
                 // Check if someone tried to skip the video
-                match PLAYLIST_THREADS.lock().unwrap().get(&room.id) {
-                    Some(status) => {
-                        playing = handle_video_event(status);
+                let event = PLAYLIST_THREADS
+                    .lock()
+                    .unwrap()
+                    .get(&room.id)
+                    .map(handle_video_event);
+
+                match event {
+                    Some(event) => {
+                        playing = event;
+                        skipped = !event;
                     }
                     None => {
                         PLAYLIST_THREADS
@@ -77,34 +115,111 @@ pub fn play_current_video(conn: &PgConnection, room: &Room) -> bool {
                     }
                     Err(e) => {
                         playing = false;
-                        println!("SystemTime elapsed error: {}", e);
+                        error!("SystemTime elapsed error: {}", e);
                     }
                 }
 
                 thread::sleep(time::Duration::from_millis(250));
             }
 
-            println!("Done playing [{}] from room [{}]", &video.title, &room.name);
+            // A stopped room keeps its current video in the queue
+            if is_stopped(room.id) {
+                event!(Level::Info, "Stopped playing room";
+                    "room_id" => room.id,
+                    "video_id" => video.id
+                );
+                stop_playing(room);
+                return false;
+            }
+
+            let message = if skipped {
+                "Skipped video"
+            } else {
+                "Finished playing video"
+            };
+
+            event!(Level::Info, message;
+                "room_id" => room.id,
+                "video_id" => video.id,
+                "youtube_id" => &video.video_id
+            );
+
+            metrics::record(|metrics| {
+                if skipped {
+                    metrics.video_skipped(room.id)
+                } else {
+                    metrics.video_played(room.id)
+                }
+            });
+
+            // Mark the video as played, skips are kept for the room statistics
+            let skipped_at = if skipped { Some(SystemTime::now()) } else { None };
 
-            // Mark the video as played
             super::diesel::update(&video)
-                .set(played.eq(true))
+                .set((played.eq(true), skipped_on.eq(skipped_at)))
                 .execute(conn)
                 .expect("Unable to mark the current video as played.");
 
             true
         }
         Err(_) => {
+            if replay_history(conn, room) {
+                return true;
+            }
+
             stop_playing(room);
             false
         }
     }
 }
 
+// Keep the room going with songs from its history when its fallback mode asks for it
+fn replay_history(conn: &PgConnection, room: &Room) -> bool {
+    use playlist::Playlist;
+    use settings::{FallbackMode, RoomSettings};
+
+    match RoomSettings::find(conn, room.id) {
+        Ok(ref settings) if settings.fallback_mode == FallbackMode::ReplayHistory => {
+            Playlist::requeue_from_history(conn, room)
+        }
+        _ => false,
+    }
+}
+
 fn handle_video_event(status: &VideoStatus) -> bool {
     match *status {
         VideoStatus::Play => true,
         VideoStatus::Skip => false,
+        VideoStatus::Stop => false,
+    }
+}
+
+fn is_stopped(room_id: i64) -> bool {
+    match PLAYLIST_THREADS.lock().unwrap().get(&room_id) {
+        Some(&VideoStatus::Stop) => true,
+        _ => false,
+    }
+}
+
+// Forgets the room of a player thread that panicked, so the next sync starts a new player for it
+struct PlayerGuard(i64);
+
+impl Drop for PlayerGuard {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        event!(Level::Error, "Player thread panicked"; "room_id" => self.0);
+
+        if let Ok(mut supervisor) = SUPERVISOR.lock() {
+            supervisor.last_panic = Some(Instant::now());
+        }
+
+        // A panic while holding the lock poisoned it, is_running reports that
+        if let Ok(mut threads) = PLAYLIST_THREADS.lock() {
+            threads.remove(&self.0);
+        }
     }
 }
 
@@ -112,13 +227,14 @@ fn handle_video_event(status: &VideoStatus) -> bool {
 pub fn play_video_thread(room: Room) {
     thread::Builder::new()
         .spawn(move || {
+            let _guard = PlayerGuard(room.id);
             let c: PgConnection = establish_connection();
 
             loop {
                 play_current_video(&c, &room);
 
                 if !PLAYLIST_THREADS.lock().unwrap().contains_key(&room.id) {
-                    println!("Stop playin thread with id: {}", room.id);
+                    event!(Level::Info, "Stopped player thread"; "room_id" => room.id);
                     break;
                 }
             }
@@ -126,19 +242,77 @@ pub fn play_video_thread(room: Room) {
         .unwrap();
 }
 
-// Loop through every room & start playing their playlists IF the playlist isn't empty.
-// At the end of the loop, start the FFA playlist(room None)
+/// Start playing every room with a queue, and keep the players in sync with the database
 pub fn init_playlist_listener() {
+    let conn: PgConnection = establish_connection();
+    sync_players(&conn);
+    record_sync();
+
+    thread::Builder::new()
+        .spawn(move || loop {
+            thread::sleep(time::Duration::from_secs(PLAYER_SYNC_INTERVAL));
+            sync_players(&conn);
+            record_sync();
+        })
+        .unwrap();
+}
+
+fn record_sync() {
+    if let Ok(mut supervisor) = SUPERVISOR.lock() {
+        supervisor.last_sync = Some(Instant::now());
+    }
+}
+
+/// How long ago the players were last synced with the database, None when they never were
+pub fn since_last_sync() -> Option<Duration> {
+    SUPERVISOR
+        .lock()
+        .ok()
+        .and_then(|supervisor| supervisor.last_sync.map(|last_sync| last_sync.elapsed()))
+}
+
+/// How long ago a player thread last panicked, None when none did
+pub fn since_last_panic() -> Option<Duration> {
+    SUPERVISOR
+        .lock()
+        .ok()
+        .and_then(|supervisor| supervisor.last_panic.map(|last_panic| last_panic.elapsed()))
+}
+
+/// Starts a player for every room with songs in its queue and stops the players of rooms that
+/// were archived or deleted. This picks up changes that were made outside of the server,
+/// eg: with `youkebox rooms import` or `youkebox rooms delete`.
+pub fn sync_players(conn: &PgConnection) {
     use self::schema::rooms::dsl::*;
 
     use playlist::Playlist;
 
-    let conn: PgConnection = establish_connection();
+    let result = rooms
+        .filter(archived_at.is_null())
+        .filter(deleted_at.is_null())
+        .load::<Room>(conn);
+
+    let active = match result {
+        Ok(active) => active,
+        Err(e) => {
+            error!("Error while loading the rooms to play: {}", e);
+            return;
+        }
+    };
 
-    let result = rooms.load::<Room>(&conn).expect("Error loading videos");
+    let playing: Vec<i64> = match PLAYLIST_THREADS.lock() {
+        Ok(threads) => threads.keys().cloned().collect(),
+        Err(_) => return,
+    };
 
-    for room in result {
-        if Playlist::is_empty(&conn, &room) {
+    for room_id in playing {
+        if !active.iter().any(|room| room.id == room_id) {
+            stop_room(room_id);
+        }
+    }
+
+    for room in active {
+        if Playlist::is_empty(conn, &room) {
             continue;
         }
         start_playing(room);
@@ -158,31 +332,83 @@ pub fn stop_playing(room: &Room) {
     PLAYLIST_THREADS.lock().unwrap().remove(&room.id);
 }
 
-// Returns a duration string as seconds
-// EG: "PT1H10M10S" -> 4210
-pub fn duration_to_seconds(duration: &str) -> u64 {
-    let v: Vec<&str> = duration.split(|c: char| !c.is_numeric()).collect();
-    let mut index: u32 = 0;
-    let mut total: i32 = 0;
+/// The amount of rooms with a running player thread
+pub fn active_rooms() -> usize {
+    PLAYLIST_THREADS.lock().map(|threads| threads.len()).unwrap_or(0)
+}
 
-    for i in (0..v.len()).rev() {
-        if !v[i].is_empty() {
-            total += v[i].parse::<i32>().unwrap() * (60i32.pow(index));
-            index += 1;
+/// A player thread that panicked while holding the lock leaves it poisoned,
+/// after which no room can be started, stopped or skipped anymore
+pub fn is_running() -> bool {
+    !PLAYLIST_THREADS.is_poisoned()
+}
+
+/// Stop the thread of a room from the outside, as soon as it notices
+pub fn stop_room(room_id: i64) {
+    if let Some(status) = PLAYLIST_THREADS.lock().unwrap().get_mut(&room_id) {
+        *status = VideoStatus::Stop;
+    }
+}
+
+/// Returns an ISO 8601 duration, the way YouTube describes the length of a video, as seconds.
+/// None when it's malformed or doesn't fit in a u64.
+/// EG: "PT1H10M10S" -> Some(4210)
+pub fn duration_to_seconds(duration: &str) -> Option<u64> {
+    let mut chars = duration.chars();
+
+    if chars.next() != Some('P') {
+        return None;
+    }
+
+    let mut total: u64 = 0;
+    let mut number: Option<u64> = None;
+    let mut in_time = false;
+    // Units have to go from large to small, each of them at most once
+    let mut last_unit = u64::max_value();
+
+    for c in chars {
+        if let Some(digit) = c.to_digit(10) {
+            let value = number.unwrap_or(0).checked_mul(10)?.checked_add(u64::from(digit))?;
+            number = Some(value);
+            continue;
+        }
+
+        let unit = match (in_time, c) {
+            (false, 'T') if number.is_none() => {
+                in_time = true;
+                continue;
+            }
+            (false, 'W') => 7 * 24 * 60 * 60,
+            (false, 'D') => 24 * 60 * 60,
+            (true, 'H') => 60 * 60,
+            (true, 'M') => 60,
+            (true, 'S') => 1,
+            _ => return None,
+        };
+
+        if unit >= last_unit {
+            return None;
         }
+        last_unit = unit;
+
+        total = total.checked_add(number.take()?.checked_mul(unit)?)?;
+    }
+
+    // A number without a unit, or no units at all: "PT10", "P" or "PT"
+    if number.is_some() || last_unit == u64::max_value() {
+        return None;
     }
 
-    total as u64
+    Some(total)
 }
 
 pub fn skip_video(room: &i64) {
     let mut rooms = PLAYLIST_THREADS.lock().unwrap();
 
-    println!("Skipping a song in room [{}]", room);
-
     if let Some(mut_key) = rooms.get_mut(room) {
+        event!(Level::Info, "Skipping the current video"; "room_id" => room);
         *mut_key = VideoStatus::Skip;
     } else {
-        println!("Invalid room, could not skip song.");
+        event!(Level::Warn, "Nothing is playing, could not skip"; "room_id" => room);
     }
 }
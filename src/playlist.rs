@@ -4,7 +4,7 @@ use diesel::pg::PgConnection;
 use rocket::http::Status;
 use rocket::response::Failure;
 use std::time::SystemTime;
-use video::Video;
+use video::{NewVideo, Video};
 use room::Room;
 
 #[derive(Serialize)]
@@ -41,7 +41,7 @@ impl Playlist {
                 })
             }
             Err(e) => {
-                println!("Error while fetching the playlist: {}", e);
+                error!("Error while fetching the playlist: {}", e);
                 Err(Failure(Status::InternalServerError))
             }
         }
@@ -62,23 +62,62 @@ impl Playlist {
 
         true
     }
-}
 
-fn get_timestamp(playlist: &[Video]) -> Option<u64> {
-    if playlist.is_empty() {
-        None
-    } else {
-        let started_on = playlist[0].started_on;
+    /// Queues a random song from the room's history again.
+    /// Returns false when nothing has been played in the room yet.
+    pub fn requeue_from_history(conn: &PgConnection, room: &Room) -> bool {
+        use diesel;
+        use diesel::dsl::sql;
+        use diesel::prelude::*;
+        use diesel::sql_types::Double;
+        use schema::videos;
+        use schema::videos::dsl::*;
+
+        let result = Video::belonging_to(room)
+            .filter(played.eq(true))
+            .order(sql::<Double>("random()"))
+            .first::<Video>(conn);
+
+        let video = match result {
+            Ok(video) => video,
+            Err(_) => return false,
+        };
 
-        let now = SystemTime::now();
-        let elapsed = now.duration_since(started_on.unwrap());
+        let new_video = NewVideo {
+            video_id: video.video_id,
+            title: video.title,
+            description: video.description,
+            room_id: room.id,
+            duration: video.duration,
+            added_on: SystemTime::now(),
+            channel: video.channel,
+            added_by: None,
+        };
 
-        match elapsed {
-            Ok(elapsed) => Some(elapsed.as_secs()),
+        let result = diesel::insert_into(videos::table)
+            .values(&new_video)
+            .execute(conn);
+
+        match result {
+            Ok(_) => true,
             Err(e) => {
-                println!("Error while calculating the playlist timestamp: {:?}", e);
-                None
+                error!("Error while replaying a song from the history: {}", e);
+                false
             }
         }
     }
 }
+
+// How far the current song is, None while nothing is playing yet.
+// The first song of a queue that was just added or revived hasn't started.
+fn get_timestamp(playlist: &[Video]) -> Option<u64> {
+    let started_on = playlist.first().and_then(|video| video.started_on)?;
+
+    match SystemTime::now().duration_since(started_on) {
+        Ok(elapsed) => Some(elapsed.as_secs()),
+        Err(e) => {
+            error!("Error while calculating the playlist timestamp: {:?}", e);
+            None
+        }
+    }
+}
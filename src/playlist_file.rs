@@ -0,0 +1,335 @@
+use chrono::{DateTime, Duration as DateDuration, NaiveDate, Utc};
+use diesel::pg::PgConnection;
+use rocket::http::hyper::header::{ContentDisposition, DispositionParam, DispositionType};
+use rocket::http::{ContentType, RawStr};
+use rocket::request::{self, FormItems, FromParam, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::Outcome;
+use std::io::Cursor;
+use std::time::SystemTime;
+
+use error::ApiError;
+use player::duration_to_seconds;
+use room::Room;
+use validation::ValidationErrors;
+use video::Video;
+
+const WATCH_URL: &str = "https://www.youtube.com/watch?v=";
+
+/// The formats a room playlist can be downloaded in, named after the last segment of the url
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaylistFormat {
+    M3u,
+    Xspf,
+    Csv,
+}
+
+/// Which part of a room to download
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaylistList {
+    Queue,
+    History,
+}
+
+/// The query string of a playlist download, eg: ?list=history&from=2026-10-01&to=2026-10-31.
+/// Unknown parameters are ignored, so invite links keep working.
+#[derive(Default)]
+pub struct PlaylistQuery {
+    pub list: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// A validated PlaylistQuery, `to` is exclusive
+#[derive(Debug, PartialEq)]
+pub struct PlaylistRange {
+    pub list: PlaylistList,
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PlaylistEntry {
+    pub url: String,
+    pub title: String,
+    /// In seconds
+    pub duration: u64,
+}
+
+/// A rendered playlist, sent as an attachment
+pub struct PlaylistFile {
+    pub format: PlaylistFormat,
+    pub filename: String,
+    pub body: String,
+}
+
+impl PlaylistFormat {
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Csv => "csv",
+        }
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match *self {
+            PlaylistFormat::M3u => ContentType::new("audio", "x-mpegurl"),
+            PlaylistFormat::Xspf => ContentType::new("application", "xspf+xml"),
+            PlaylistFormat::Csv => ContentType::with_params("text", "csv", ("charset", "utf-8")),
+        }
+    }
+
+    pub fn render(&self, title: &str, entries: &[PlaylistEntry]) -> String {
+        match *self {
+            PlaylistFormat::M3u => render_m3u(entries),
+            PlaylistFormat::Xspf => render_xspf(title, entries),
+            PlaylistFormat::Csv => render_csv(entries),
+        }
+    }
+}
+
+impl<'a> FromParam<'a> for PlaylistFormat {
+    type Error = &'a RawStr;
+
+    fn from_param(param: &'a RawStr) -> Result<PlaylistFormat, &'a RawStr> {
+        match param.as_str() {
+            "playlist.m3u" => Ok(PlaylistFormat::M3u),
+            "playlist.xspf" => Ok(PlaylistFormat::Xspf),
+            "playlist.csv" => Ok(PlaylistFormat::Csv),
+            _ => Err(param),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PlaylistQuery {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<PlaylistQuery, ()> {
+        let mut query = PlaylistQuery::default();
+
+        for (key, value) in FormItems::from(request.uri().query().unwrap_or("")) {
+            let value = value.url_decode().ok();
+
+            match key.as_str() {
+                "list" => query.list = value,
+                "from" => query.from = value,
+                "to" => query.to = value,
+                _ => {}
+            }
+        }
+
+        Outcome::Success(query)
+    }
+}
+
+impl PlaylistQuery {
+    /// Dates are either a day (2026-10-01) or an RFC 3339 timestamp, both in UTC.
+    /// A day as `to` includes the whole day.
+    pub fn range(&self) -> Result<PlaylistRange, ApiError> {
+        let mut errors = ValidationErrors::new();
+
+        let list = match self.list.as_ref().map(|l| &l[..]) {
+            None | Some("history") => PlaylistList::History,
+            Some("queue") => PlaylistList::Queue,
+            Some(_) => {
+                errors.add("list", "Must be one of: queue, history.");
+                PlaylistList::History
+            }
+        };
+
+        let from = parse_time(&mut errors, "from", self.from.as_ref(), false);
+        let to = parse_time(&mut errors, "to", self.to.as_ref(), true);
+
+        if let (Some(from), Some(to)) = (from, to) {
+            if to <= from {
+                errors.add("to", "Must be after from.");
+            }
+        }
+
+        errors.into_result()?;
+
+        Ok(PlaylistRange { list, from, to })
+    }
+}
+
+fn parse_time(
+    errors: &mut ValidationErrors,
+    field: &str,
+    value: Option<&String>,
+    end_of_day: bool,
+) -> Option<SystemTime> {
+    let value = value?;
+
+    if let Ok(day) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let mut start = day.and_hms(0, 0, 0);
+        if end_of_day {
+            start += DateDuration::days(1);
+        }
+        return Some(DateTime::<Utc>::from_utc(start, Utc).into());
+    }
+
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(time) => Some(time.into()),
+        Err(_) => {
+            errors.add(field, "Must be a date (2026-10-01) or an RFC 3339 timestamp.");
+            None
+        }
+    }
+}
+
+impl<'a> From<&'a Video> for PlaylistEntry {
+    fn from(video: &Video) -> PlaylistEntry {
+        PlaylistEntry {
+            url: format!("{}{}", WATCH_URL, video.video_id),
+            title: video.title.clone(),
+            duration: duration_to_seconds(&video.duration).unwrap_or(0),
+        }
+    }
+}
+
+impl PlaylistFile {
+    /// The queue in the order it will be played, or the history in the order it was played.
+    /// The range applies to when a video was added to the queue, or when it started playing.
+    #[inline]
+    pub fn create(
+        conn: &PgConnection,
+        room: &Room,
+        format: PlaylistFormat,
+        range: &PlaylistRange,
+    ) -> Result<PlaylistFile, ApiError> {
+        use diesel::prelude::*;
+        use schema::videos::dsl::*;
+
+        let mut result = Video::belonging_to(room).into_boxed();
+
+        result = match range.list {
+            PlaylistList::Queue => {
+                result = result.filter(played.eq(false)).order(id.asc());
+                if let Some(from) = range.from {
+                    result = result.filter(added_on.ge(from));
+                }
+                if let Some(to) = range.to {
+                    result = result.filter(added_on.lt(to));
+                }
+                result
+            }
+            PlaylistList::History => {
+                result = result
+                    .filter(played.eq(true))
+                    .order((started_on.asc(), id.asc()));
+                if let Some(from) = range.from {
+                    result = result.filter(started_on.ge(from));
+                }
+                if let Some(to) = range.to {
+                    result = result.filter(started_on.lt(to));
+                }
+                result
+            }
+        };
+
+        let entries: Vec<PlaylistEntry> = result
+            .load::<Video>(conn)?
+            .iter()
+            .map(PlaylistEntry::from)
+            .collect();
+
+        let list = match range.list {
+            PlaylistList::Queue => "queue",
+            PlaylistList::History => "history",
+        };
+
+        Ok(PlaylistFile {
+            format,
+            filename: format!("{}-{}.{}", room.slug, list, format.extension()),
+            body: format.render(&format!("{} ({})", room.name, list), &entries),
+        })
+    }
+}
+
+impl<'r> Responder<'r> for PlaylistFile {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        let disposition = ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Ext("filename".to_string(), self.filename)],
+        };
+
+        Response::build()
+            .header(self.format.content_type())
+            .header(disposition)
+            .sized_body(Cursor::new(self.body))
+            .ok()
+    }
+}
+
+// Titles end up on a single line in M3U, so line breaks become spaces
+fn single_line(value: &str) -> String {
+    value.replace(|c| c == '\r' || c == '\n', " ")
+}
+
+fn render_m3u(entries: &[PlaylistEntry]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+
+    for entry in entries {
+        m3u.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            entry.duration,
+            single_line(&entry.title),
+            entry.url
+        ));
+    }
+
+    m3u
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// XSPF durations are in milliseconds
+fn render_xspf(title: &str, entries: &[PlaylistEntry]) -> String {
+    let mut xspf = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xspf.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    xspf.push_str(&format!("  <title>{}</title>\n", escape_xml(title)));
+    xspf.push_str("  <trackList>\n");
+
+    for entry in entries {
+        xspf.push_str(&format!(
+            "    <track>\n      <location>{}</location>\n      <title>{}</title>\n      <duration>{}</duration>\n    </track>\n",
+            escape_xml(&entry.url),
+            escape_xml(&entry.title),
+            entry.duration.saturating_mul(1000)
+        ));
+    }
+
+    xspf.push_str("  </trackList>\n</playlist>\n");
+    xspf
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_csv(entries: &[PlaylistEntry]) -> String {
+    let mut csv = String::from("url,title,duration\r\n");
+
+    for entry in entries {
+        csv.push_str(&format!(
+            "{},{},{}\r\n",
+            escape_csv(&entry.url),
+            escape_csv(&entry.title),
+            entry.duration
+        ));
+    }
+
+    csv
+}
@@ -1,17 +1,57 @@
 use diesel;
-use diesel::pg::PgConnection;
-use regex::Regex;
+use diesel::pg::{Pg, PgConnection};
+use lower;
 use rocket::http::Status;
 use rocket::response::Failure;
 use super::schema::rooms;
-use player::play_video_thread;
+use auth::{generate_token, RoomAccessToken, RoomCredentials};
+use error::ApiError;
+use listener::{active_since, RoomListener};
+use bcrypt::{hash, verify, DEFAULT_COST};
+use serde::Serializer;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use member::RoomMember;
+use names::{skeleton, NameRules};
+use player::{start_playing, stop_room};
+use picture;
+use playlist::Playlist;
+use slug::{slugify, RoomSlug};
+use storage::Storage;
+use user::User;
+use validation::{check_range, nullable, parse_bool, parse_number, ValidationErrors};
+use video::Video;
 
-#[derive(Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[derive(Clone, Serialize, Queryable, Identifiable)]
 pub struct Room {
     pub id: i64,
     pub name: String,
     pub description: Option<String>,
     pub is_public: bool,
+    pub owner_id: Option<i64>,
+    // Only handed out to the owner through the invite endpoints
+    #[serde(skip_serializing)]
+    pub invite_token: String,
+    // The bcrypt hash of the join password, clients only get to see whether there is one
+    #[serde(rename = "has_password", serialize_with = "serialize_is_some")]
+    pub password_hash: Option<String>,
+    pub created_at: SystemTime,
+    /// The last time a song was added, started playing or was skipped
+    pub last_activity_at: SystemTime,
+    /// Set when the room was archived for being idle for too long
+    pub archived_at: Option<SystemTime>,
+    /// Set when the room was deleted, it can be restored until it gets purged
+    pub deleted_at: Option<SystemTime>,
+    /// Derived from the name, used in /rooms/by-name/<slug> links
+    pub slug: String,
+    // Used to keep look-alike names apart
+    #[serde(skip_serializing)]
+    pub name_skeleton: Option<String>,
+    // Relative to the pictures directory, clients only get to see whether there is one
+    #[serde(rename = "has_picture", serialize_with = "serialize_is_some")]
+    pub picture_path: Option<String>,
+    #[serde(skip_serializing)]
+    pub picture_type: Option<String>,
 }
 
 #[derive(Insertable, Deserialize)]
@@ -20,26 +60,178 @@ pub struct NewRoom {
     pub name: String,
     pub description: Option<String>,
     pub is_public: bool,
+    #[serde(skip_deserializing)]
+    pub owner_id: Option<i64>,
+    #[serde(skip_deserializing)]
+    pub slug: String,
+    #[serde(skip_deserializing)]
+    pub name_skeleton: Option<String>,
 }
 
-#[derive(FromForm)]
-pub struct SearchRoom {
-    pub name: String,
+/// A partial room update, fields that are left out stay untouched
+#[derive(Deserialize, AsChangeset)]
+#[table_name = "rooms"]
+pub struct RoomPatch {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub description: Option<Option<String>>,
+    pub is_public: Option<bool>,
+    // Follows the name, never set by clients
+    #[serde(skip_deserializing)]
+    pub slug: Option<String>,
+    #[serde(skip_deserializing)]
+    pub name_skeleton: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Invite {
+    pub room_id: i64,
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct RoomPassword {
+    pub password: Option<String>,
+}
+
+fn serialize_is_some<S: Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(value.is_some())
+}
+
+/// The query string of the room listing, eg: /rooms?name=death&sort=activity&limit=10
+/// Everything is taken as a string, so invalid values are reported instead of ignored.
+#[derive(FromForm, Default)]
+pub struct RoomQuery {
+    pub name: Option<String>,
+    pub limit: Option<String>,
+    pub offset: Option<String>,
+    /// name, created, activity or listeners
+    pub sort: Option<String>,
+    /// asc or desc
+    pub order: Option<String>,
+    /// Only rooms that do (true) or don't (false) have songs queued
+    pub playing: Option<String>,
+    /// Only rooms that do (true) or don't (false) have a picture
+    pub has_picture: Option<String>,
+}
+
+/// A validated RoomQuery
+#[derive(Debug, PartialEq)]
+pub struct RoomFilter {
+    pub name: Option<String>,
+    pub limit: i64,
+    pub offset: i64,
+    pub sort: RoomSort,
+    pub descending: bool,
+    pub playing: Option<bool>,
+    pub has_picture: Option<bool>,
+}
+
+/// A room in the listing, together with the song it's playing right now
+#[derive(Serialize)]
+pub struct RoomListing {
+    #[serde(flatten)]
+    pub room: Room,
+    pub now_playing: Option<Video>,
+    /// How many people sent a heartbeat lately
+    pub listeners: i64,
+}
+
+#[derive(Serialize)]
+pub struct RoomPage {
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    pub rooms: Vec<RoomListing>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoomSort {
+    Name,
+    Created,
+    Activity,
+    Listeners,
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+impl RoomQuery {
+    pub fn filter(&self) -> Result<RoomFilter, ApiError> {
+        let mut errors = ValidationErrors::new();
+
+        let limit = parse_number(&mut errors, "limit", self.limit.as_ref(), DEFAULT_PAGE_SIZE);
+        check_range(&mut errors, "limit", limit, 1, MAX_PAGE_SIZE);
+
+        let offset = parse_number(&mut errors, "offset", self.offset.as_ref(), 0);
+        check_range(&mut errors, "offset", offset, 0, i64::max_value());
+
+        let sort = match self.sort.as_ref().map(|s| &s[..]) {
+            None | Some("name") => RoomSort::Name,
+            Some("created") => RoomSort::Created,
+            Some("activity") => RoomSort::Activity,
+            Some("listeners") => RoomSort::Listeners,
+            Some(_) => {
+                errors.add("sort", "Must be one of: name, created, activity, listeners.");
+                RoomSort::Name
+            }
+        };
+
+        // Names read alphabetically, the others newest or busiest first
+        let descending = match self.order.as_ref().map(|o| &o[..]) {
+            None => sort != RoomSort::Name,
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(_) => {
+                errors.add("order", "Must be one of: asc, desc.");
+                false
+            }
+        };
+
+        let playing = parse_bool(&mut errors, "playing", self.playing.as_ref());
+        let has_picture = parse_bool(&mut errors, "has_picture", self.has_picture.as_ref());
+
+        errors.into_result()?;
+
+        Ok(RoomFilter {
+            name: self.name.clone(),
+            limit,
+            offset,
+            sort,
+            descending,
+            playing,
+            has_picture,
+        })
+    }
 }
 
 impl Room {
     #[inline]
-    pub fn create(conn: &PgConnection, mut new_room: NewRoom) -> Result<Room, Failure> {
+    pub fn create(
+        conn: &PgConnection,
+        rules: &NameRules,
+        mut new_room: NewRoom,
+    ) -> Result<Room, ApiError> {
         use diesel::prelude::*;
-        use diesel::result::Error;
+        use diesel::result::{DatabaseErrorKind, Error};
 
-        new_room.name = new_room.name.trim().to_string();
+        match rules.check(&new_room.name) {
+            Ok(name) => new_room.name = name,
+            Err(message) => {
+                let mut errors = ValidationErrors::new();
+                errors.add("name", &message);
+                return Err(errors.into());
+            }
+        }
 
-        let regex = Regex::new(r"^[[:word:]]{3,20}$").unwrap();
+        let name_skeleton = skeleton(&new_room.name);
+        Room::check_name_available(conn, &name_skeleton, None)?;
 
-        if !regex.is_match(&new_room.name) {
-            return Err(Failure(Status::BadRequest));
-        }
+        new_room.name_skeleton = Some(name_skeleton);
+        new_room.slug = RoomSlug::available(conn, &slugify(&new_room.name), None);
 
         // I add the type here because othwerise the clone() doesn't know which type it is.
         let created_room: Result<Room, Error> = diesel::insert_into(rooms::table)
@@ -48,64 +240,306 @@ impl Room {
 
         match created_room {
             Ok(room) => {
-                play_video_thread(room.clone());
+                RoomSlug::record(conn, room.id, &room.slug)?;
+                if let Some(owner) = room.owner_id {
+                    RoomMember::add(conn, room.id, owner)?;
+                }
                 Ok(room)
             }
-            Err(_) => Err(Failure(Status::Conflict)),
+            Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(name_taken("Is already taken.").into())
+            }
+            Err(e) => Err(ApiError::Database(e)),
         }
     }
 
     #[inline]
-    pub fn update(conn: &PgConnection, room: &Room) -> Result<Room, Failure> {
+    pub fn update(
+        conn: &PgConnection,
+        rules: &NameRules,
+        room: &Room,
+        mut patch: RoomPatch,
+    ) -> Result<Room, ApiError> {
         use diesel::prelude::*;
+        use diesel::result::{DatabaseErrorKind, Error};
         use schema::rooms::dsl::*;
 
-        let regex = Regex::new(r"^[[:word:]]{3,20}$").unwrap();
+        let mut errors = ValidationErrors::new();
 
-        if !regex.is_match(&room.name) {
-            return Err(Failure(Status::BadRequest));
+        if let Some(ref mut new_name) = patch.name {
+            match rules.check(new_name) {
+                Ok(normalized) => *new_name = normalized,
+                Err(message) => errors.add("name", &message),
+            }
         }
 
-        let result = diesel::update(rooms)
-            .set((
-                description.eq(room.description.clone()),
-                name.eq(room.name.clone()),
-                is_public.eq(room.is_public),
-            ))
-            .get_result(conn);
+        if patch.is_public == Some(false) && room.owner_id.is_none() {
+            errors.add("is_public", "Only rooms with an owner can be private.");
+        }
+
+        errors.into_result()?;
+
+        if patch.name.is_none() && patch.description.is_none() && patch.is_public.is_none() {
+            return Ok(room.clone());
+        }
+
+        // The old slug stays in room_slugs so it keeps redirecting
+        if let Some(ref new_name) = patch.name {
+            let new_skeleton = skeleton(new_name);
+            Room::check_name_available(conn, &new_skeleton, Some(room.id))?;
+            patch.name_skeleton = Some(new_skeleton);
+
+            let base = slugify(new_name);
+
+            if base != room.slug {
+                patch.slug = Some(RoomSlug::available(conn, &base, Some(room.id)));
+            }
+        }
+
+        let result = diesel::update(rooms.filter(id.eq(room.id)))
+            .set(&patch)
+            .get_result::<Room>(conn);
 
         match result {
-            Ok(updated_room) => Ok(updated_room),
-            Err(_) => Err(Failure(Status::Conflict)),
+            Ok(updated_room) => {
+                if updated_room.slug != room.slug {
+                    RoomSlug::record(conn, updated_room.id, &updated_room.slug)?;
+                }
+                Ok(updated_room)
+            }
+            Err(Error::NotFound) => Err(ApiError::NotFound),
+            Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(name_taken("Is already taken.").into())
+            }
+            Err(e) => Err(ApiError::Database(e)),
         }
     }
 
+    // Rejects names that look like the name of another room, eg: "room" and "r00m"
+    fn check_name_available(
+        conn: &PgConnection,
+        name_skeleton: &str,
+        except_room: Option<i64>,
+    ) -> Result<(), ApiError> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl;
+
+        let existing = dsl::rooms
+            .select(dsl::name)
+            .filter(dsl::name_skeleton.eq(name_skeleton))
+            .filter(dsl::deleted_at.is_null())
+            .filter(dsl::id.ne(except_room.unwrap_or(-1)))
+            .first::<String>(conn)
+            .optional()
+            .map_err(internal_error)?;
+
+        match existing {
+            Some(existing) => Err(name_taken(&format!(
+                "Is already taken or looks too much like the room \"{}\".",
+                existing
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // Soft delete a room: it stops playing and disappears, but its queue,
+    // history and picture are kept until it gets purged.
     #[inline]
     pub fn delete(conn: &PgConnection, room_id: i64) -> Result<(), Failure> {
         use diesel::prelude::*;
+        use diesel::result::Error;
         use schema::rooms::dsl::*;
-        use std::fs;
 
-        let result = diesel::delete(rooms.filter(id.eq(room_id))).execute(conn);
+        let result = diesel::update(rooms.filter(id.eq(room_id)).filter(deleted_at.is_null()))
+            .set(deleted_at.eq(SystemTime::now()))
+            .get_result::<Room>(conn);
+
+        match result {
+            Ok(_) => {
+                stop_room(room_id);
+                Ok(())
+            }
+            Err(Error::NotFound) => Err(Failure(Status::NotFound)),
+            Err(_) => Err(Failure(Status::InternalServerError)),
+        }
+    }
+
+    // Bring back a room that was deleted less than `retention` ago
+    #[inline]
+    pub fn restore(conn: &PgConnection, room_id: i64, retention: Duration) -> Result<Room, Failure> {
+        use diesel::prelude::*;
+        use diesel::result::{DatabaseErrorKind, Error};
+        use schema::rooms::dsl::*;
+
+        let since = SystemTime::now() - retention;
+
+        let result = diesel::update(rooms.filter(id.eq(room_id)).filter(deleted_at.gt(since)))
+            .set(deleted_at.eq(None::<SystemTime>))
+            .get_result::<Room>(conn);
+
+        match result {
+            Ok(room) => {
+                RoomSlug::record(conn, room.id, &room.slug)?;
+                if !Playlist::is_empty(conn, &room) {
+                    start_playing(room.clone());
+                }
+                Ok(room)
+            }
+            Err(Error::NotFound) => Err(Failure(Status::NotFound)),
+            // Another room took the name in the meantime
+            Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(Failure(Status::Conflict))
+            }
+            Err(_) => Err(Failure(Status::InternalServerError)),
+        }
+    }
+
+    // Permanently remove a room, its videos and its picture
+    #[inline]
+    pub fn purge(conn: &PgConnection, storage: &Storage, room_id: i64) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        let result = diesel::delete(rooms.filter(id.eq(room_id))).get_result::<Room>(conn);
+
+        let room = match result {
+            Ok(room) => room,
+            Err(_) => return Err(Failure(Status::InternalServerError)),
+        };
+
+        stop_room(room_id);
+
+        if let Some(ref path) = room.picture_path {
+            picture::remove(&*storage.0, path)?;
+        }
+
+        Ok(())
+    }
+
+    // Return every room, including archived and deleted ones
+    #[inline]
+    pub fn all(conn: &PgConnection) -> Result<Vec<Room>, Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        rooms.order(id).load::<Room>(conn).map_err(internal_error)
+    }
+
+    // Return the soft deleted rooms, the most recently deleted first
+    #[inline]
+    pub fn deleted(conn: &PgConnection) -> Result<Vec<Room>, Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        rooms
+            .filter(deleted_at.is_not_null())
+            .order(deleted_at.desc())
+            .load::<Room>(conn)
+            .map_err(internal_error)
+    }
+
+    // Rooms that were soft deleted before `since`
+    #[inline]
+    pub fn deleted_before(conn: &PgConnection, since: SystemTime) -> Result<Vec<Room>, Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        rooms
+            .filter(deleted_at.lt(since))
+            .load::<Room>(conn)
+            .map_err(internal_error)
+    }
+
+    // Hide an idle room from the listing, the picture, queue and history are kept
+    // so the room is intact when new activity brings it back
+    #[inline]
+    pub fn archive(conn: &PgConnection, room: &Room) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        let result = diesel::update(rooms.filter(id.eq(room.id)))
+            .set(archived_at.eq(SystemTime::now()))
+            .execute(conn);
 
         if result.is_err() {
             return Err(Failure(Status::InternalServerError));
         }
 
-        let picture_url = format!("{}/{}", *super::PICTURES_DIR, room_id).to_string();
-
-        let _res = fs::remove_file(picture_url);
+        stop_room(room.id);
 
         Ok(())
     }
 
+    // Mark a room as active right now, this also brings archived rooms back
+    #[inline]
+    pub fn touch(conn: &PgConnection, room_id: i64) {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        let result = diesel::update(rooms.filter(id.eq(room_id)))
+            .set((
+                last_activity_at.eq(SystemTime::now()),
+                archived_at.eq(None::<SystemTime>),
+            ))
+            .execute(conn);
+
+        if let Err(e) = result {
+            error!("Error while updating the activity of room {}: {}", room_id, e);
+        }
+    }
+
+    // Rooms that haven't seen any activity since `since`
+    #[inline]
+    pub fn idle(conn: &PgConnection, since: SystemTime) -> Result<Vec<Room>, Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        rooms
+            .filter(last_activity_at.lt(since))
+            .filter(archived_at.is_null())
+            .filter(deleted_at.is_null())
+            .order(last_activity_at.asc())
+            .load::<Room>(conn)
+            .map_err(internal_error)
+    }
+
+    // Record where the picture of a room is stored and what type it is, None clears it
+    #[inline]
+    pub fn set_picture(
+        conn: &PgConnection,
+        room_id: i64,
+        picture: Option<(&str, &str)>,
+    ) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        let result = diesel::update(rooms.filter(id.eq(room_id)))
+            .set((
+                picture_path.eq(picture.map(|(path, _)| path)),
+                picture_type.eq(picture.map(|(_, mime)| mime)),
+            ))
+            .execute(conn);
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error while saving the picture of room {}: {}", room_id, e);
+                Err(Failure(Status::InternalServerError))
+            }
+        }
+    }
+
     // Find & return a room by id
     #[inline]
     pub fn find(conn: &PgConnection, room_id: i64) -> Option<Room> {
         use diesel::prelude::*;
         use schema::rooms::dsl::*;
 
-        let result = rooms.filter(id.eq(room_id)).first::<Room>(conn);
+        let result = rooms
+            .filter(id.eq(room_id))
+            .filter(deleted_at.is_null())
+            .first::<Room>(conn);
 
         match result {
             Ok(result) => Some(result),
@@ -113,32 +547,317 @@ impl Room {
         }
     }
 
-    // Return all rooms
+    // Find a room by its current slug or by one it had before being renamed
     #[inline]
-    pub fn all(conn: &PgConnection, query: Option<String>) -> Result<Vec<Room>, Failure> {
+    pub fn find_by_slug(conn: &PgConnection, room_slug: &str) -> Option<Room> {
         use diesel::prelude::*;
         use schema::rooms::dsl::*;
 
-        let result;
+        let current = rooms
+            .filter(slug.eq(room_slug))
+            .filter(deleted_at.is_null())
+            .first::<Room>(conn)
+            .ok();
+
+        current.or_else(|| Room::find(conn, RoomSlug::room_id(conn, room_slug)?))
+    }
+
+    // Find a room the credentials give access to.
+    // Private rooms are reported as missing so their existence doesn't leak.
+    #[inline]
+    pub fn find_accessible(
+        conn: &PgConnection,
+        room_id: i64,
+        credentials: &RoomCredentials,
+    ) -> Result<Room, Failure> {
+        match Room::find(conn, room_id) {
+            Some(ref room) if room.is_accessible(conn, credentials) => Ok(room.clone()),
+            _ => Err(Failure(Status::NotFound)),
+        }
+    }
+
+    // Find a room the credentials allow to change.
+    // Rooms with an owner can only be changed by that owner.
+    #[inline]
+    pub fn find_editable(
+        conn: &PgConnection,
+        room_id: i64,
+        credentials: &RoomCredentials,
+    ) -> Result<Room, Failure> {
+        let room = Room::find_accessible(conn, room_id, credentials)?;
+
+        if room.owner_id.is_none() {
+            return Ok(room);
+        }
+
+        match credentials.user {
+            Some(ref user) if room.owner_id == Some(user.id) => Ok(room),
+            Some(_) => Err(Failure(Status::Forbidden)),
+            None => Err(Failure(Status::Unauthorized)),
+        }
+    }
+
+    // Find a room that belongs to the given user
+    #[inline]
+    pub fn find_owned(conn: &PgConnection, room_id: i64, user: &User) -> Result<Room, Failure> {
+        let room = match Room::find(conn, room_id) {
+            Some(room) => room,
+            None => return Err(Failure(Status::NotFound)),
+        };
+
+        if room.owner_id != Some(user.id) {
+            return Err(Failure(Status::Forbidden));
+        }
+
+        Ok(room)
+    }
+
+    /// Public rooms are open to everyone, private rooms only to their owner,
+    /// their members and whoever holds the room's invite token.
+    pub fn is_accessible(&self, conn: &PgConnection, credentials: &RoomCredentials) -> bool {
+        if self.is_public {
+            return true;
+        }
 
-        match query {
-            Some(query) => {
-                result = rooms
-                    .filter(name.ilike(format!("%{}%", query.to_lowercase())))
-                    .order(name.desc())
-                    .load::<Room>(conn);
+        if let Some(ref token) = credentials.invite_token {
+            if *token == self.invite_token {
+                return true;
             }
-            None => {
-                result = rooms.order(id.asc()).load::<Room>(conn);
+        }
+
+        match credentials.user {
+            Some(ref user) => {
+                self.owner_id == Some(user.id) || RoomMember::exists(conn, self.id, user.id)
             }
+            None => false,
         }
+    }
 
-        match result {
-            Ok(result) => Ok(result),
-            Err(e) => {
-                println!("Error while fetching the rooms: {}", e);
-                Err(Failure(Status::InternalServerError))
+    /// Password protected rooms only let their owner and holders of
+    /// a valid room access token add or skip songs.
+    pub fn check_access_token(
+        &self,
+        conn: &PgConnection,
+        credentials: &RoomCredentials,
+    ) -> Result<(), Failure> {
+        if self.password_hash.is_none() {
+            return Ok(());
+        }
+
+        if let Some(ref user) = credentials.user {
+            if self.owner_id == Some(user.id) {
+                return Ok(());
             }
         }
+
+        match credentials.access_token {
+            Some(ref token) if RoomAccessToken::is_valid(conn, self.id, token) => Ok(()),
+            _ => Err(Failure(Status::Unauthorized)),
+        }
+    }
+
+    // Trade the join password for a room access token
+    #[inline]
+    pub fn unlock(&self, conn: &PgConnection, attempt: &str) -> Result<RoomAccessToken, Failure> {
+        let matches = match self.password_hash {
+            Some(ref password_hash) => verify(attempt, password_hash).unwrap_or(false),
+            None => return Err(Failure(Status::BadRequest)),
+        };
+
+        if !matches {
+            return Err(Failure(Status::Unauthorized));
+        }
+
+        RoomAccessToken::create(conn, self.id)
+    }
+
+    // Set or clear (None) the join password, this revokes all handed out access tokens
+    #[inline]
+    pub fn set_password(
+        conn: &PgConnection,
+        room_id: i64,
+        new_password: Option<&str>,
+    ) -> Result<Room, Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        let hashed = match new_password {
+            Some(new_password) => match hash(new_password, DEFAULT_COST) {
+                Ok(hashed) => Some(hashed),
+                Err(e) => {
+                    error!("Error while hashing a room password: {}", e);
+                    return Err(Failure(Status::InternalServerError));
+                }
+            },
+            None => None,
+        };
+
+        RoomAccessToken::delete_all(conn, room_id)?;
+
+        let result = diesel::update(rooms.filter(id.eq(room_id)))
+            .set(password.eq(hashed))
+            .get_result(conn);
+
+        match result {
+            Ok(room) => Ok(room),
+            Err(_) => Err(Failure(Status::InternalServerError)),
+        }
+    }
+
+    // Replace the invite token, every previously shared invite link stops working
+    #[inline]
+    pub fn regenerate_invite_token(conn: &PgConnection, room_id: i64) -> Result<Room, Failure> {
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        let result = diesel::update(rooms.filter(id.eq(room_id)))
+            .set(invite_token.eq(generate_token(32)))
+            .get_result(conn);
+
+        match result {
+            Ok(room) => Ok(room),
+            Err(_) => Err(Failure(Status::InternalServerError)),
+        }
+    }
+
+    /// Returns a page of public rooms, together with the song each of them is playing
+    #[inline]
+    pub fn list(conn: &PgConnection, query: &RoomQuery) -> Result<RoomPage, ApiError> {
+        use diesel::dsl::sql;
+        use diesel::prelude::*;
+        use diesel::sql_types::{BigInt, Timestamp};
+        use schema::rooms::dsl::*;
+
+        let filter = query.filter()?;
+
+        let total = Room::filtered(&filter)
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(internal_error)?;
+
+        let mut page = Room::filtered(&filter);
+
+        // The amount of people listening to each room, counted in the outer query
+        let listener_count = || {
+            sql::<BigInt>(
+                "(SELECT count(*) FROM room_listeners \
+                 WHERE room_listeners.room_id = rooms.id AND room_listeners.last_seen >= ",
+            ).bind::<Timestamp, _>(active_since())
+                .sql(")")
+        };
+
+        page = match (filter.sort, filter.descending) {
+            (RoomSort::Name, false) => page.order(lower(name).asc()),
+            (RoomSort::Name, true) => page.order(lower(name).desc()),
+            (RoomSort::Created, false) => page.order(created_at.asc()),
+            (RoomSort::Created, true) => page.order(created_at.desc()),
+            (RoomSort::Activity, false) => page.order(last_activity_at.asc()),
+            (RoomSort::Activity, true) => page.order(last_activity_at.desc()),
+            (RoomSort::Listeners, false) => page.order(listener_count().asc()),
+            (RoomSort::Listeners, true) => page.order(listener_count().desc()),
+        };
+
+        let result = page
+            .then_order_by(id.asc())
+            .limit(filter.limit)
+            .offset(filter.offset)
+            .load::<Room>(conn)
+            .map_err(internal_error)?;
+
+        let mut now_playing = Room::now_playing(conn, &result)?;
+        let room_ids: Vec<i64> = result.iter().map(|room| room.id).collect();
+        let listeners = RoomListener::counts(conn, &room_ids)?;
+
+        let listings = result
+            .into_iter()
+            .map(|room| RoomListing {
+                now_playing: now_playing.remove(&room.id),
+                listeners: listeners.get(&room.id).cloned().unwrap_or(0),
+                room,
+            })
+            .collect();
+
+        Ok(RoomPage {
+            total,
+            limit: filter.limit,
+            offset: filter.offset,
+            rooms: listings,
+        })
+    }
+
+    // The public rooms that match the filters of a query, without ordering or paging
+    fn filtered(filter: &RoomFilter) -> rooms::BoxedQuery<'static, Pg> {
+        use diesel::dsl::not;
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+        use schema::videos;
+
+        let mut result = rooms
+            .filter(is_public.eq(true))
+            .filter(archived_at.is_null())
+            .filter(deleted_at.is_null())
+            .into_boxed();
+
+        if let Some(ref search) = filter.name {
+            result = result.filter(name.ilike(format!("%{}%", search.to_lowercase())));
+        }
+
+        if let Some(playing) = filter.playing {
+            let queued = videos::table
+                .select(videos::room_id)
+                .filter(videos::played.eq(false));
+
+            result = if playing {
+                result.filter(id.eq_any(queued))
+            } else {
+                result.filter(not(id.eq_any(queued)))
+            };
+        }
+
+        if let Some(has_picture) = filter.has_picture {
+            result = if has_picture {
+                result.filter(picture_path.is_not_null())
+            } else {
+                result.filter(picture_path.is_null())
+            };
+        }
+
+        result
     }
+
+    // The current video of every room in the list that is playing something, in a single query
+    fn now_playing(
+        conn: &PgConnection,
+        room_list: &[Room],
+    ) -> Result<HashMap<i64, Video>, Failure> {
+        use diesel::prelude::*;
+        use schema::videos::dsl::*;
+
+        let room_ids: Vec<i64> = room_list.iter().map(|room| room.id).collect();
+
+        let result = videos
+            .filter(room_id.eq_any(room_ids))
+            .filter(played.eq(false))
+            .order((room_id, id))
+            .distinct_on(room_id)
+            .load::<Video>(conn)
+            .map_err(internal_error)?;
+
+        Ok(result
+            .into_iter()
+            .map(|video| (video.room_id, video))
+            .collect())
+    }
+}
+
+// A 409 that tells which name field clashed and why
+fn name_taken(message: &str) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add("name", message);
+    errors.with_status(Status::Conflict)
+}
+
+fn internal_error(e: diesel::result::Error) -> Failure {
+    error!("Error while fetching the rooms: {}", e);
+    Failure(Status::InternalServerError)
 }
@@ -1,23 +1,39 @@
 #![allow(unknown_lints, needless_pass_by_value)]
 
 use DbConn;
+use Pool;
 
-use bytes::BufMut;
-use image;
-use image::GenericImage;
-use rocket::http::Status;
-use rocket::response::{content, status, Failure, NamedFile, Redirect};
+use rocket::http::uri::URI;
+use rocket::http::{ContentType, Status};
+use rocket::response::{content, status, Content, Redirect};
 use rocket::Data;
 use rocket::State;
 use rocket_contrib::Json;
 use serde_json;
-use std::fs::File;
-use std::path::Path;
 
+use auth::*;
+use error::ApiError;
+use export::{RoomExport, RoomImport};
+use favourite::{ExportedPlaylist, Favourite, FavouritesExport, YoutubePlaylists};
+use health::{self, Readiness};
 use http::HttpStatus;
-use player::skip_video;
+use janitor::JanitorConfig;
+use listener::{Heartbeat, ListenerConfig, ListenerId, RoomListener, RoomListeners};
+use member::RoomMember;
+use metrics;
+use names::NameRules;
+use picture::{self, Picture};
+use player::{play_video_thread, skip_video, start_playing};
 use playlist::*;
+use playlist_file::{PlaylistFile, PlaylistFormat, PlaylistQuery};
 use room::*;
+use saved_playlist::{SavedPlaylist, SavedPlaylistDetails, SavedPlaylistForm, SavedPlaylistPatch};
+use settings::*;
+use stats::{RoomStats, StatsQuery};
+use storage::{PictureStorage, Storage};
+use trending::{Trending, TrendingConfig, TrendingQuery};
+use user::{NewUser, User};
+use validation::ValidationErrors;
 use video::*;
 use youtube::*;
 
@@ -26,6 +42,35 @@ fn index() -> Redirect {
     Redirect::to("/api/v1/")
 }
 
+// Prometheus metrics, mounted outside of the api
+#[get("/metrics")]
+fn show_metrics(pool: State<Pool>) -> Content<String> {
+    Content(
+        ContentType::with_params("text", "plain", ("version", "0.0.4")),
+        metrics::render(&pool),
+    )
+}
+
+// Liveness, answers as long as Rocket is able to handle requests
+#[get("/health")]
+fn health() -> Json<HttpStatus> {
+    Json(HttpStatus::new(Status::Ok, "OK"))
+}
+
+// Readiness, answers with a 503 while one of the checks fails
+#[get("/ready")]
+fn ready(pool: State<Pool>, api_key: State<ApiKey>) -> status::Custom<Json<Readiness>> {
+    let readiness = health::readiness(&pool, &api_key);
+
+    let status = if readiness.is_ready() {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+
+    status::Custom(status, Json(readiness))
+}
+
 #[get("/")]
 fn api_index() -> &'static str {
     "
@@ -41,7 +86,7 @@ fn api_index() -> &'static str {
 fn search_video(
     api_key: State<ApiKey>,
     query: YoutubeQuery,
-) -> Result<content::Json<String>, Failure> {
+) -> Result<content::Json<String>, ApiError> {
     let res = YoutubeVideo::search(&api_key.0.clone(), &query.query[..])?;
 
     Ok(content::Json(res))
@@ -49,29 +94,112 @@ fn search_video(
 
 // Rooms
 #[get("/rooms")]
-fn show_rooms(conn: DbConn) -> Json<Vec<Room>> {
-    let rooms = Room::all(&conn, None).unwrap();
-    Json(rooms)
+fn show_rooms(conn: DbConn) -> Result<Json<RoomPage>, ApiError> {
+    let rooms = Room::list(&conn, &RoomQuery::default())?;
+    Ok(Json(rooms))
+}
+
+#[get("/rooms?<query>")]
+fn search_rooms(conn: DbConn, query: RoomQuery) -> Result<Json<RoomPage>, ApiError> {
+    let rooms = Room::list(&conn, &query)?;
+    Ok(Json(rooms))
+}
+
+// The songs that are hot across the public rooms, computed in the background by the trending job
+#[get("/trending")]
+fn show_trending(conn: DbConn, config: State<TrendingConfig>) -> Result<Json<Trending>, ApiError> {
+    let trending = Trending::get(&conn, &config, &TrendingQuery::default())?;
+    Ok(Json(trending))
 }
 
-#[get("/rooms?<room>")]
-fn search_rooms(conn: DbConn, room: SearchRoom) -> Json<Vec<Room>> {
-    let rooms = Room::all(&conn, Some(room.name))?;
-    Json(rooms)
+#[get("/trending?<query>")]
+fn search_trending(
+    conn: DbConn,
+    config: State<TrendingConfig>,
+    query: TrendingQuery,
+) -> Result<Json<Trending>, ApiError> {
+    let trending = Trending::get(&conn, &config, &query)?;
+    Ok(Json(trending))
 }
 
 #[get("/rooms/<id>")]
-fn show_room(conn: DbConn, id: i64) -> Option<Json<Room>> {
-    let room = Room::find(&conn, id)?;
+fn show_room(conn: DbConn, id: i64, credentials: RoomCredentials) -> Option<Json<Room>> {
+    let room = Room::find_accessible(&conn, id, &credentials).ok()?;
+
+    Some(Json(room))
+}
+
+// Ranked below the /rooms/<id>/... routes, which forward since "by-name" isn't an id.
+// Old slugs redirect to the current one.
+#[get("/rooms/by-name/<slug>", rank = 2)]
+fn show_room_by_slug(
+    conn: DbConn,
+    slug: String,
+    credentials: RoomCredentials,
+) -> Result<Result<Json<Room>, Redirect>, ApiError> {
+    let room = match Room::find_by_slug(&conn, &slug) {
+        Some(room) => room,
+        None => return Err(ApiError::NotFound),
+    };
+
+    if !room.is_accessible(&conn, &credentials) {
+        return Err(ApiError::NotFound);
+    }
+
+    if room.slug == slug {
+        return Ok(Ok(Json(room)));
+    }
+
+    let mut location = format!("/api/v1/rooms/by-name/{}", URI::percent_encode(&room.slug));
+
+    if let Some(ref token) = credentials.invite_token {
+        location.push_str(&format!("?invite={}", URI::percent_encode(token)));
+    }
 
-    Json(r)
+    Ok(Err(Redirect::moved(&location)))
 }
 
 // Return a playlist for a room
 #[get("/rooms/<id>/playlist")]
-fn get_playlist(conn: DbConn, id: i64) -> Result<Json<Playlist>, Failure> {
+fn get_playlist(
+    conn: DbConn,
+    id: i64,
+    credentials: RoomCredentials,
+) -> Result<Json<Playlist>, ApiError> {
+    Room::find_accessible(&conn, id, &credentials)?;
+
     let playlist = Playlist::get(&conn, id)?;
-    Json(playlist)
+    Ok(Json(playlist))
+}
+
+// The queue or history of a room as an M3U, XSPF or CSV file, eg: /rooms/4/playlist.m3u?list=queue.
+// Ranked below every other /rooms/<id>/... route, it forwards anything that isn't a playlist file.
+#[get("/rooms/<id>/<format>", rank = 3)]
+fn get_playlist_file(
+    conn: DbConn,
+    id: i64,
+    format: PlaylistFormat,
+    query: PlaylistQuery,
+    credentials: RoomCredentials,
+) -> Result<PlaylistFile, ApiError> {
+    let room = Room::find_accessible(&conn, id, &credentials)?;
+    let range = query.range()?;
+
+    PlaylistFile::create(&conn, &room, format, &range)
+}
+
+// What a room played lately, eg: /rooms/4/stats?days=7&limit=10 for the top 10 of the week
+#[get("/rooms/<id>/stats")]
+fn get_room_stats(
+    conn: DbConn,
+    id: i64,
+    query: StatsQuery,
+    credentials: RoomCredentials,
+) -> Result<Json<RoomStats>, ApiError> {
+    let room = Room::find_accessible(&conn, id, &credentials)?;
+    let (days, limit) = query.parse()?;
+
+    Ok(Json(RoomStats::compute(&conn, &room, days, limit)?))
 }
 
 // Add a song to a room
@@ -81,148 +209,614 @@ fn add_video(
     conn: DbConn,
     id_list: String,
     room: i64,
-) -> Result<status::Created<Json<Vec<Video>>>, Failure> {
-    let videos: Vec<String> = serde_json::from_str(&id_list)?;
-    let result = YoutubeVideo::get(&api_key.0.clone(), &conn, &videos, room)?;
-    status::Created("".to_string(), Json(result))
+    credentials: RoomCredentials,
+) -> Result<status::Created<Json<Vec<Video>>>, ApiError> {
+    Room::find_accessible(&conn, room, &credentials)?.check_access_token(&conn, &credentials)?;
+
+    let videos: Vec<String> =
+        serde_json::from_str(&id_list).map_err(|_| ApiError::Status(Status::BadRequest))?;
+    let added_by = credentials.user.as_ref().map(|user| user.id);
+    let result = YoutubeVideo::get(&api_key.0.clone(), &conn, &videos, room, added_by)?;
+    Ok(status::Created("".to_string(), Some(Json(result))))
 }
 
+// Private rooms need an owner, so only logged in users can create them
 #[post("/rooms", format = "application/json", data = "<room>")]
-fn add_room(conn: DbConn, room: Json<NewRoom>) -> Result<Json<Room>, Failure> {
-    let room = Room::create(&conn, room.into_inner())?;
-    Json(room)
+fn add_room(
+    conn: DbConn,
+    room: Json<NewRoom>,
+    user: Option<AuthUser>,
+    rules: State<NameRules>,
+) -> Result<Json<Room>, ApiError> {
+    let mut room = room.into_inner();
+
+    match user {
+        Some(user) => room.owner_id = Some(user.0.id),
+        None if !room.is_public => return Err(ApiError::Unauthorized),
+        None => {}
+    }
+
+    let room = Room::create(&conn, &rules, room)?;
+    play_video_thread(room.clone());
+
+    Ok(Json(room))
 }
 
 // TODO:
-// Create the picture when the room is created
+// Create the picture when the room is created, rooms with an owner only take it from that owner
 #[post("/rooms/<id>/picture", data = "<picture_stream>")]
-fn set_room_picture(id: i64, picture_stream: Data) -> Result<String, Failure> {
-    use establish_connection;
-    let con = establish_connection();
+fn set_room_picture(
+    conn: DbConn,
+    id: i64,
+    picture_stream: Data,
+    credentials: RoomCredentials,
+    storage: State<Storage>,
+) -> Result<String, ApiError> {
+    Room::find_editable(&conn, id, &credentials)?;
 
-    if Room::find(&con, id).is_none() {
-        return Err(Failure(Status::NotFound));
-    }
+    // Uploads over the limit are rejected before anything gets decoded
+    let upload = picture::read_upload(picture_stream.open())?;
+    metrics::record(|metrics| metrics.picture_uploaded(upload.len()));
 
-    // 262144 bytes = max filesize for a 512x512 png
-    let mut buf = Vec::with_capacity(262_144).writer();
-    let res = picture_stream.stream_to(&mut buf);
+    // Larger pictures are scaled down and everything is stored as PNG
+    let processed = picture::process(&upload)?;
+    let picture_path = picture::save(&*storage.0, id, &processed)?;
 
-    if res.is_err() {
-        return Err(Failure(Status::InternalServerError));
-    }
+    Room::set_picture(&conn, id, Some((&picture_path, picture::PICTURE_TYPE)))?;
 
-    let im = image::load_from_memory(buf.get_ref());
-    let picture;
-    let image_format;
-    match im {
-        Ok(im) => {
-            picture = im;
-            image_format = image::guess_format(buf.get_ref());
-        }
-        Err(_e) => return Err(Failure(Status::UnsupportedMediaType)),
+    Ok(format!("/rooms/{}/picture", id))
+}
+
+#[get("/rooms/<id>/picture")]
+fn get_room_picture(
+    conn: DbConn,
+    id: i64,
+    credentials: RoomCredentials,
+    storage: State<Storage>,
+) -> Result<Picture, ApiError> {
+    let room = Room::find_accessible(&conn, id, &credentials)?;
+
+    room_picture(&*storage.0, &room, None)
+}
+
+#[get("/rooms/<id>/picture/<size>")]
+fn get_room_thumbnail(
+    conn: DbConn,
+    id: i64,
+    size: u32,
+    credentials: RoomCredentials,
+    storage: State<Storage>,
+) -> Result<Picture, ApiError> {
+    let room = Room::find_accessible(&conn, id, &credentials)?;
+
+    if !picture::THUMBNAIL_SIZES.contains(&size) {
+        return Err(ApiError::NotFound);
     }
 
-    if picture.width() > 512 || picture.height() > 512 {
-        return Err(Failure(Status::BadRequest));
+    room_picture(&*storage.0, &room, Some(size))
+}
+
+// Load the picture of a room, or one of its thumbnails
+fn room_picture(
+    storage: &PictureStorage,
+    room: &Room,
+    size: Option<u32>,
+) -> Result<Picture, ApiError> {
+    let path = match room.picture_path {
+        Some(ref path) => path,
+        None => return Err(ApiError::NotFound),
+    };
+
+    let data = match size {
+        Some(size) => storage.get(&picture::thumbnail_path(path, size))?,
+        None => storage.get(path)?,
+    };
+
+    let content_type = room
+        .picture_type
+        .as_ref()
+        .and_then(|mime| mime.parse::<ContentType>().ok())
+        .unwrap_or(ContentType::Binary);
+
+    match data {
+        Some(data) => Ok(Picture {
+            data,
+            content_type,
+            public: room.is_public,
+        }),
+        None => Err(ApiError::NotFound),
     }
+}
 
-    let picture_url = format!("{}/{}", *super::PICTURES_DIR, id).to_string();
-    let picture_path = Path::new(&picture_url);
+#[get("/rooms/<id>/settings")]
+fn get_room_settings(
+    conn: DbConn,
+    id: i64,
+    credentials: RoomCredentials,
+) -> Result<Json<RoomSettings>, ApiError> {
+    Room::find_accessible(&conn, id, &credentials)?;
 
-    let fout = &mut File::create(&picture_path).unwrap();
-    let result = picture.save(fout, image_format.unwrap());
+    let settings = RoomSettings::find(&conn, id)?;
+    Ok(Json(settings))
+}
 
-    match result {
-        Ok(_r) => Ok(format!("/rooms/{}/picture", id)),
-        Err(e) => {
-            println!("Failed to save the picture: {}", e);
-            Err(Failure(Status::InternalServerError))
+// Rooms with an owner can only be configured by that owner
+#[patch("/rooms/<id>/settings", format = "application/json", data = "<settings>")]
+fn update_room_settings(
+    conn: DbConn,
+    id: i64,
+    settings: Json<RoomSettingsPatch>,
+    credentials: RoomCredentials,
+) -> Result<Json<RoomSettings>, ApiError> {
+    Room::find_editable(&conn, id, &credentials)?;
+
+    let settings = RoomSettings::update(&conn, id, &settings)?;
+    Ok(Json(settings))
+}
+
+// Share this token as `/rooms/<id>?invite=<token>` to let people into a private room
+#[get("/rooms/<id>/invite")]
+fn get_room_invite(conn: DbConn, id: i64, user: AuthUser) -> Result<Json<Invite>, ApiError> {
+    let room = Room::find_owned(&conn, id, &user.0)?;
+
+    Ok(Json(Invite {
+        room_id: room.id,
+        token: room.invite_token,
+    }))
+}
+
+// Revoke the current invite links by generating a new token
+#[post("/rooms/<id>/invite")]
+fn reset_room_invite(conn: DbConn, id: i64, user: AuthUser) -> Result<Json<Invite>, ApiError> {
+    Room::find_owned(&conn, id, &user.0)?;
+    let room = Room::regenerate_invite_token(&conn, id)?;
+
+    Ok(Json(Invite {
+        room_id: room.id,
+        token: room.invite_token,
+    }))
+}
+
+// Set or remove (null) the join password of a room
+#[put("/rooms/<id>/password", format = "application/json", data = "<password>")]
+fn set_room_password(
+    conn: DbConn,
+    id: i64,
+    password: Json<RoomPassword>,
+    user: AuthUser,
+) -> Result<Json<Room>, ApiError> {
+    Room::find_owned(&conn, id, &user.0)?;
+
+    if let Some(ref password) = password.password {
+        if password.len() < 4 {
+            let mut errors = ValidationErrors::new();
+            errors.add("password", "Must be at least 4 characters long.");
+            return Err(errors.into());
         }
     }
+
+    let room = Room::set_password(&conn, id, password.password.as_ref().map(|p| &p[..]))?;
+    Ok(Json(room))
 }
 
-// TODO:
-// Set the picture url in the room table
-// this way, we could do NamedFile::open(Path::new(room.picture))
-// Because right now, we don't know if it's a jpg or png
-#[get("/rooms/<id>/picture")]
-fn get_room_picture(id: i64) -> Option<NamedFile> {
-    let picture_url = format!("content/rooms/pictures/{}", id).to_string();
-    NamedFile::open(Path::new(&picture_url)).ok()
+// Exchange the join password for a short-lived token, sent back in the X-Room-Token header
+#[post("/rooms/<id>/access", format = "application/json", data = "<password>")]
+fn unlock_room(
+    conn: DbConn,
+    id: i64,
+    password: Json<RoomPassword>,
+    credentials: RoomCredentials,
+) -> Result<Json<RoomAccessToken>, ApiError> {
+    let room = Room::find_accessible(&conn, id, &credentials)?;
+    let attempt = password.password.as_ref().map(|p| &p[..]).unwrap_or("");
+
+    let access_token = room.unlock(&conn, attempt)?;
+    Ok(Json(access_token))
+}
+
+// Become a member of a room, private rooms need a valid invite token
+#[post("/rooms/<id>/join")]
+fn join_room(
+    conn: DbConn,
+    id: i64,
+    user: AuthUser,
+    credentials: RoomCredentials,
+) -> Result<Json<HttpStatus>, ApiError> {
+    Room::find_accessible(&conn, id, &credentials)?;
+    RoomMember::add(&conn, id, user.0.id)?;
+
+    Ok(Json(HttpStatus::new(Status::Ok, "Successfully joined the room.")))
 }
 
-#[put("/rooms", format = "application/json", data = "<room>")]
-fn update_room(conn: DbConn, room: Json<Room>) -> Result<Json<Room>, Failure> {
-    let result = Room::update(&conn, &room.into_inner());
+#[get("/rooms/<id>/members")]
+fn show_room_members(
+    conn: DbConn,
+    id: i64,
+    user: AuthUser,
+) -> Result<Json<Vec<RoomMember>>, ApiError> {
+    Room::find_owned(&conn, id, &user.0)?;
 
-    match result {
-        Ok(new_room) => Ok(Json(new_room)),
-        Err(e) => Err(e),
+    let members = RoomMember::all(&conn, id)?;
+    Ok(Json(members))
+}
+
+// Clients send this about every 30 seconds while they're listening to a room.
+// Anonymous listeners send back the X-Listener-Id they got, they're only counted once they do.
+#[post("/rooms/<id>/heartbeat")]
+fn room_heartbeat(
+    conn: DbConn,
+    id: i64,
+    listener_id: ListenerId,
+    credentials: RoomCredentials,
+    config: State<ListenerConfig>,
+) -> Result<Json<Heartbeat>, ApiError> {
+    Room::find_accessible(&conn, id, &credentials)?;
+
+    let heartbeat = RoomListener::heartbeat(
+        &conn,
+        &config,
+        id,
+        credentials.user.as_ref(),
+        &listener_id,
+    )?;
+    Ok(Json(heartbeat))
+}
+
+// Who is listening to a room right now, anonymous listeners are only counted
+#[get("/rooms/<id>/listeners")]
+fn show_room_listeners(
+    conn: DbConn,
+    id: i64,
+    credentials: RoomCredentials,
+) -> Result<Json<RoomListeners>, ApiError> {
+    Room::find_accessible(&conn, id, &credentials)?;
+
+    Ok(Json(RoomListener::active(&conn, id)?))
+}
+
+// Owners can remove anyone, members can only leave themselves
+#[delete("/rooms/<id>/members/<user_id>")]
+fn delete_room_member(
+    conn: DbConn,
+    id: i64,
+    user_id: i64,
+    user: AuthUser,
+) -> Result<Json<HttpStatus>, ApiError> {
+    if user.0.id != user_id {
+        Room::find_owned(&conn, id, &user.0)?;
     }
+
+    RoomMember::remove(&conn, id, user_id)?;
+
+    Ok(Json(HttpStatus::new(Status::Ok, "Successfully removed the member.")))
+}
+
+// Only the fields present in the body are updated
+#[patch("/rooms/<id>", format = "application/json", data = "<room>")]
+fn update_room(
+    conn: DbConn,
+    id: i64,
+    room: Json<RoomPatch>,
+    credentials: RoomCredentials,
+    rules: State<NameRules>,
+) -> Result<Json<Room>, ApiError> {
+    let current = Room::find_editable(&conn, id, &credentials)?;
+    let updated = Room::update(&conn, &rules, &current, room.into_inner())?;
+
+    Ok(Json(updated))
 }
 
+// Rooms are only soft deleted, an admin can restore them during the retention period
 #[delete("/rooms/<id>")]
-fn delete_room(conn: DbConn, id: i64) -> Result<Json<HttpStatus>, Failure> {
+fn delete_room(
+    conn: DbConn,
+    id: i64,
+    credentials: RoomCredentials,
+) -> Result<Json<HttpStatus>, ApiError> {
+    Room::find_editable(&conn, id, &credentials)?;
+
     let result = Room::delete(&conn, id);
 
     match result {
-        Ok(_result) => Ok(Json(HttpStatus {
-            status: 200,
-            message: "Successfully removed the room.".to_string(),
-        })),
-        Err(e) => Err(e),
+        Ok(_result) => Ok(Json(HttpStatus::new(Status::Ok, "Successfully removed the room."))),
+        Err(e) => Err(e.into()),
     }
 }
 
+// The room with its settings, picture, queue and history, to be read back by /rooms/import
+#[get("/rooms/<id>/export")]
+fn export_room(
+    conn: DbConn,
+    id: i64,
+    credentials: RoomCredentials,
+    storage: State<Storage>,
+) -> Result<Json<RoomExport>, ApiError> {
+    let room = Room::find_accessible(&conn, id, &credentials)?;
+
+    Ok(Json(RoomExport::create(&conn, &*storage.0, &room)?))
+}
+
+// Recreates an exported room under a new id, owned by whoever imports it
+#[post("/rooms/import", format = "application/json", data = "<export>")]
+fn import_room(
+    conn: DbConn,
+    export: Data,
+    user: Option<AuthUser>,
+    rules: State<NameRules>,
+    storage: State<Storage>,
+) -> Result<status::Created<Json<Room>>, ApiError> {
+    let import = RoomImport::read(export.open())?;
+
+    let owner_id = match user {
+        Some(user) => Some(user.0.id),
+        None if !import.room.is_public => return Err(ApiError::Unauthorized),
+        None => None,
+    };
+
+    let room = import.import(&conn, &*storage.0, &rules, owner_id)?;
+
+    if !Playlist::is_empty(&conn, &room) {
+        start_playing(room.clone());
+    }
+
+    Ok(status::Created(format!("/rooms/{}", room.id), Some(Json(room))))
+}
+
+#[get("/rooms/deleted")]
+fn show_deleted_rooms(conn: DbConn, _admin: AdminUser) -> Result<Json<Vec<Room>>, ApiError> {
+    Ok(Json(Room::deleted(&conn)?))
+}
+
+// Restores a deleted room together with its queue and history
+#[post("/rooms/<id>/restore")]
+fn restore_room(
+    conn: DbConn,
+    id: i64,
+    config: State<JanitorConfig>,
+    _admin: AdminUser,
+) -> Result<Json<Room>, ApiError> {
+    Ok(Json(Room::restore(&conn, id, config.retention())?))
+}
+
 // Skip a song in a room
 #[post("/rooms/<id>/skip")]
-fn skip_song_in_room(id: i64) -> Json<HttpStatus> {
+fn skip_song_in_room(
+    conn: DbConn,
+    id: i64,
+    credentials: RoomCredentials,
+) -> Result<Json<HttpStatus>, ApiError> {
+    Room::find_accessible(&conn, id, &credentials)?.check_access_token(&conn, &credentials)?;
+
+    let settings = RoomSettings::find(&conn, id)?;
+
+    if settings.skip_policy == SkipPolicy::Disabled {
+        return Err(ApiError::Forbidden);
+    }
+
     skip_video(&id);
+    Room::touch(&conn, id);
+
+    Ok(Json(HttpStatus::new(Status::Ok, "Successfully skipped the song")))
+}
+
+// Users
+#[post("/users", format = "application/json", data = "<user>")]
+fn register(
+    conn: DbConn,
+    user: Json<NewUser>,
+    rules: State<NameRules>,
+) -> Result<Json<User>, ApiError> {
+    let mut user = user.into_inner();
+    let mut errors = ValidationErrors::new();
+
+    match User::verify_name(&rules, &user.username) {
+        Ok(name) => user.username = name,
+        Err(message) => errors.add("username", &message),
+    }
+
+    if let Err(message) = User::verify_password(&user.password) {
+        errors.add("password", &message);
+    }
+
+    errors.into_result()?;
+
+    let user = User::create(&conn, user)?;
+    Ok(Json(user))
+}
+
+#[post("/login", format = "application/json", data = "<login>")]
+fn login(conn: DbConn, login: Json<Login>) -> Result<Json<Session>, ApiError> {
+    let user = User::authenticate(&conn, &login.username, &login.password)?;
+    let session = Session::create(&conn, &user)?;
+
+    Ok(Json(session))
+}
+
+#[post("/logout")]
+fn logout(conn: DbConn, token: BearerToken) -> Result<Json<HttpStatus>, ApiError> {
+    Session::delete(&conn, &token.0)?;
+
+    Ok(Json(HttpStatus::new(Status::Ok, "Successfully logged out.")))
+}
+
+// Likes the song that is playing in the room right now
+#[post("/rooms/<id>/favourite")]
+fn add_favourite(
+    conn: DbConn,
+    id: i64,
+    user: AuthUser,
+    credentials: RoomCredentials,
+) -> Result<status::Created<Json<Favourite>>, ApiError> {
+    let room = Room::find_accessible(&conn, id, &credentials)?;
+    let favourite = Favourite::add_now_playing(&conn, &user.0, &room)?;
+
+    Ok(status::Created("/favourites".to_string(), Some(Json(favourite))))
+}
+
+#[get("/favourites")]
+fn show_favourites(conn: DbConn, user: AuthUser) -> Result<Json<Vec<Favourite>>, ApiError> {
+    Ok(Json(Favourite::list(&conn, user.0.id)?))
+}
+
+#[delete("/favourites/<video_id>")]
+fn delete_favourite(
+    conn: DbConn,
+    video_id: String,
+    user: AuthUser,
+) -> Result<Json<HttpStatus>, ApiError> {
+    Favourite::remove(&conn, user.0.id, &video_id)?;
+
+    Ok(Json(HttpStatus::new(Status::Ok, "Successfully removed the favourite.")))
+}
+
+// Copies the favourites to a new private playlist on the user's YouTube account
+#[post("/favourites/youtube", format = "application/json", data = "<export>")]
+fn export_favourites(
+    conn: DbConn,
+    export: Json<FavouritesExport>,
+    user: AuthUser,
+    youtube: State<YoutubePlaylists>,
+) -> Result<status::Created<Json<ExportedPlaylist>>, ApiError> {
+    let favourites = Favourite::list(&conn, user.0.id)?;
+
+    if favourites.is_empty() {
+        let mut errors = ValidationErrors::new();
+        errors.add("favourites", "There is nothing to export yet.");
+        return Err(errors.into());
+    }
+
+    let title = export.title.as_ref().map_or("YoukeBox favourites", |title| &title[..]);
+    let playlist = youtube.export(&export.access_token, title, &favourites)?;
+
+    Ok(status::Created(playlist.url.clone(), Some(Json(playlist))))
+}
+
+// Saved playlists, only their owner gets to see them
+#[get("/playlists")]
+fn show_saved_playlists(
+    conn: DbConn,
+    user: AuthUser,
+) -> Result<Json<Vec<SavedPlaylistDetails>>, ApiError> {
+    Ok(Json(SavedPlaylist::list(&conn, user.0.id)?))
+}
+
+#[get("/playlists/<id>")]
+fn show_saved_playlist(
+    conn: DbConn,
+    id: i64,
+    user: AuthUser,
+) -> Result<Json<SavedPlaylistDetails>, ApiError> {
+    let playlist = SavedPlaylist::find_owned(&conn, id, &user.0)?;
+
+    Ok(Json(playlist.details(&conn)?))
+}
+
+#[post("/playlists", format = "application/json", data = "<playlist>")]
+fn add_saved_playlist(
+    api_key: State<ApiKey>,
+    conn: DbConn,
+    playlist: Json<SavedPlaylistForm>,
+    user: AuthUser,
+) -> Result<status::Created<Json<SavedPlaylistDetails>>, ApiError> {
+    let playlist = SavedPlaylist::create(&conn, &api_key.0, &user.0, &playlist)?;
+
+    Ok(status::Created(
+        format!("/playlists/{}", playlist.playlist.id),
+        Some(Json(playlist)),
+    ))
+}
+
+#[patch("/playlists/<id>", format = "application/json", data = "<patch>")]
+fn update_saved_playlist(
+    api_key: State<ApiKey>,
+    conn: DbConn,
+    id: i64,
+    patch: Json<SavedPlaylistPatch>,
+    user: AuthUser,
+) -> Result<Json<SavedPlaylistDetails>, ApiError> {
+    let playlist = SavedPlaylist::find_owned(&conn, id, &user.0)?;
+
+    Ok(Json(SavedPlaylist::update(&conn, &api_key.0, &playlist, &patch)?))
+}
+
+#[delete("/playlists/<id>")]
+fn delete_saved_playlist(conn: DbConn, id: i64, user: AuthUser) -> Result<Json<HttpStatus>, ApiError> {
+    let playlist = SavedPlaylist::find_owned(&conn, id, &user.0)?;
+    SavedPlaylist::delete(&conn, playlist.id)?;
+
+    Ok(Json(HttpStatus::new(Status::Ok, "Successfully removed the playlist.")))
+}
+
+// Queues every song of a saved playlist, checked like songs that are added one by one
+#[post("/playlists/<id>/queue/<room>")]
+fn queue_saved_playlist(
+    conn: DbConn,
+    id: i64,
+    room: i64,
+    user: AuthUser,
+    credentials: RoomCredentials,
+) -> Result<status::Created<Json<Vec<Video>>>, ApiError> {
+    let playlist = SavedPlaylist::find_owned(&conn, id, &user.0)?;
+
+    let room = Room::find_accessible(&conn, room, &credentials)?;
+    room.check_access_token(&conn, &credentials)?;
+
+    let result = playlist.queue(&conn, room)?;
+    Ok(status::Created("".to_string(), Some(Json(result))))
+}
+
+// Error pages, rendered like every other error
+#[catch(400)]
+fn bad_request() -> ApiError {
+    ApiError::Status(Status::BadRequest)
+}
+
+#[catch(401)]
+fn unauthorized() -> ApiError {
+    ApiError::Unauthorized
+}
+
+#[catch(403)]
+fn forbidden() -> ApiError {
+    ApiError::Forbidden
+}
+
+#[catch(404)]
+fn not_found() -> ApiError {
+    ApiError::NotFound
+}
+
+#[catch(409)]
+fn conflict() -> ApiError {
+    ApiError::Status(Status::Conflict)
+}
 
-    Json(HttpStatus {
-        status: 200,
-        message: "Successfully skipped the song".to_string(),
-    })
+#[catch(413)]
+fn payload_too_large() -> ApiError {
+    ApiError::Status(Status::PayloadTooLarge)
 }
 
-// Error pages
-#[error(400)]
-fn bad_request() -> Json<HttpStatus> {
-    Json(HttpStatus {
-        status: 400,
-        message: "Bad Request".to_string(),
-    })
+#[catch(415)]
+fn unsupported_media_type() -> ApiError {
+    ApiError::Status(Status::UnsupportedMediaType)
 }
 
-#[error(404)]
-fn not_found() -> Json<HttpStatus> {
-    Json(HttpStatus {
-        status: 404,
-        message: "The requested resource was not found".to_string(),
-    })
+#[catch(422)]
+fn unprocessable_entity() -> ApiError {
+    ApiError::Status(Status::UnprocessableEntity)
 }
 
-#[error(409)]
-fn conflict() -> Json<HttpStatus> {
-    Json(HttpStatus {
-        status: 409,
-        message: "Conflict".to_string(),
-    })
+#[catch(429)]
+fn too_many_requests() -> ApiError {
+    ApiError::Status(Status::TooManyRequests)
 }
 
-#[error(415)]
-fn unsupported_media_type() -> Json<HttpStatus> {
-    Json(HttpStatus {
-        status: 409,
-        message: "Unsupported Media Type".to_string(),
-    })
+#[catch(500)]
+fn internal_error() -> ApiError {
+    ApiError::Status(Status::InternalServerError)
 }
 
-#[error(500)]
-fn internal_error() -> Json<HttpStatus> {
-    Json(HttpStatus {
-        status: 500,
-        message: "Internal Server Error".to_string(),
-    })
+#[catch(503)]
+fn service_unavailable() -> ApiError {
+    ApiError::Status(Status::ServiceUnavailable)
 }
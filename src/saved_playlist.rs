@@ -0,0 +1,354 @@
+use diesel;
+use diesel::pg::PgConnection;
+use rocket::http::Status;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use error::ApiError;
+use room::Room;
+use schema::{saved_playlist_videos, saved_playlists};
+use user::User;
+use validation::ValidationErrors;
+use video::{NewVideo, Video};
+use youtube::{YoutubeVideo, MAX_VIDEOS_PER_CALL};
+
+/// Longest name a saved playlist can have, in characters
+pub const MAX_PLAYLIST_NAME_LENGTH: usize = 100;
+/// Most songs a saved playlist can hold
+pub const MAX_PLAYLIST_VIDEOS: usize = 200;
+
+/// A list of songs a user saved to queue again later, only visible to that user
+#[derive(Serialize, Queryable, Identifiable, Associations)]
+#[belongs_to(User)]
+pub struct SavedPlaylist {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+/// A song in a saved playlist, with the metadata YouTube had when it was saved
+#[derive(Serialize, Queryable, Identifiable, Associations)]
+#[belongs_to(SavedPlaylist, foreign_key = "playlist_id")]
+#[table_name = "saved_playlist_videos"]
+pub struct SavedVideo {
+    #[serde(skip_serializing)]
+    pub id: i64,
+    #[serde(skip_serializing)]
+    pub playlist_id: i64,
+    pub video_id: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub duration: String,
+    pub channel: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SavedPlaylistDetails {
+    #[serde(flatten)]
+    pub playlist: SavedPlaylist,
+    pub videos: Vec<SavedVideo>,
+}
+
+/// The body of a new playlist, eg: { name: "metal", videos: [ "ZnJVcuUDnW4" ] }
+#[derive(Deserialize)]
+pub struct SavedPlaylistForm {
+    pub name: String,
+    #[serde(default)]
+    pub videos: Vec<String>,
+}
+
+/// Only the fields that are present get updated, `videos` replaces every song
+#[derive(Deserialize)]
+pub struct SavedPlaylistPatch {
+    pub name: Option<String>,
+    pub videos: Option<Vec<String>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "saved_playlists"]
+struct NewSavedPlaylist<'a> {
+    user_id: i64,
+    name: &'a str,
+}
+
+// A song as YouTube describes it, before it's saved
+struct Song {
+    video_id: String,
+    title: String,
+    description: Option<String>,
+    duration: String,
+    channel: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "saved_playlist_videos"]
+struct NewSavedVideo {
+    playlist_id: i64,
+    video_id: String,
+    title: String,
+    description: Option<String>,
+    duration: String,
+    channel: Option<String>,
+}
+
+impl SavedPlaylist {
+    /// Every playlist of a user with its songs, in the order they were created
+    #[inline]
+    pub fn list(conn: &PgConnection, u_id: i64) -> Result<Vec<SavedPlaylistDetails>, ApiError> {
+        use diesel::prelude::*;
+        use schema::saved_playlists::dsl::*;
+
+        let playlists = saved_playlists
+            .filter(user_id.eq(u_id))
+            .order(id)
+            .load::<SavedPlaylist>(conn)?;
+
+        let songs = SavedVideo::belonging_to(&playlists)
+            .order(saved_playlist_videos::id)
+            .load::<SavedVideo>(conn)?
+            .grouped_by(&playlists);
+
+        Ok(playlists
+            .into_iter()
+            .zip(songs)
+            .map(|(playlist, videos)| SavedPlaylistDetails { playlist, videos })
+            .collect())
+    }
+
+    /// Other users' playlists answer with a 404 as if they didn't exist
+    #[inline]
+    pub fn find_owned(conn: &PgConnection, p_id: i64, user: &User) -> Result<SavedPlaylist, ApiError> {
+        use diesel::prelude::*;
+        use schema::saved_playlists::dsl::*;
+
+        saved_playlists
+            .filter(id.eq(p_id))
+            .filter(user_id.eq(user.id))
+            .first::<SavedPlaylist>(conn)
+            .optional()?
+            .ok_or(ApiError::NotFound)
+    }
+
+    #[inline]
+    pub fn details(self, conn: &PgConnection) -> Result<SavedPlaylistDetails, ApiError> {
+        let videos = self.videos(conn)?;
+
+        Ok(SavedPlaylistDetails {
+            playlist: self,
+            videos,
+        })
+    }
+
+    #[inline]
+    pub fn videos(&self, conn: &PgConnection) -> Result<Vec<SavedVideo>, ApiError> {
+        use diesel::prelude::*;
+
+        SavedVideo::belonging_to(self)
+            .order(saved_playlist_videos::id)
+            .load::<SavedVideo>(conn)
+            .map_err(Into::into)
+    }
+
+    /// The songs are looked up on YouTube once, their metadata is kept with the playlist
+    #[inline]
+    pub fn create(
+        conn: &PgConnection,
+        api_key: &str,
+        user: &User,
+        form: &SavedPlaylistForm,
+    ) -> Result<SavedPlaylistDetails, ApiError> {
+        use diesel::prelude::*;
+
+        let name = check_name(&form.name)?;
+        let songs = lookup(api_key, &form.videos)?;
+
+        conn.transaction(|| {
+            let playlist = diesel::insert_into(saved_playlists::table)
+                .values(&NewSavedPlaylist {
+                    user_id: user.id,
+                    name: &name,
+                })
+                .get_result::<SavedPlaylist>(conn)
+                .map_err(name_conflict)?;
+
+            SavedPlaylist::insert_videos(conn, playlist.id, songs)?;
+
+            playlist.details(conn)
+        })
+    }
+
+    #[inline]
+    pub fn update(
+        conn: &PgConnection,
+        api_key: &str,
+        playlist: &SavedPlaylist,
+        patch: &SavedPlaylistPatch,
+    ) -> Result<SavedPlaylistDetails, ApiError> {
+        use diesel::prelude::*;
+        use schema::saved_playlists::dsl::*;
+
+        let new_name = match patch.name {
+            Some(ref new_name) => Some(check_name(new_name)?),
+            None => None,
+        };
+
+        let songs = match patch.videos {
+            Some(ref ids) => Some(lookup(api_key, ids)?),
+            None => None,
+        };
+
+        conn.transaction(|| {
+            if let Some(new_name) = new_name {
+                diesel::update(saved_playlists.find(playlist.id))
+                    .set(name.eq(new_name))
+                    .execute(conn)
+                    .map_err(name_conflict)?;
+            }
+
+            if let Some(songs) = songs {
+                diesel::delete(SavedVideo::belonging_to(playlist)).execute(conn)?;
+                SavedPlaylist::insert_videos(conn, playlist.id, songs)?;
+            }
+
+            diesel::update(saved_playlists.find(playlist.id))
+                .set(updated_at.eq(SystemTime::now()))
+                .get_result::<SavedPlaylist>(conn)?
+                .details(conn)
+        })
+    }
+
+    #[inline]
+    pub fn delete(conn: &PgConnection, p_id: i64) -> Result<(), ApiError> {
+        use diesel::prelude::*;
+        use schema::saved_playlists::dsl::*;
+
+        match diesel::delete(saved_playlists.find(p_id)).execute(conn)? {
+            0 => Err(ApiError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds every song of the playlist to the queue of a room, checked against the room settings
+    /// like any other song. The cached metadata is used, so YouTube isn't asked again.
+    #[inline]
+    pub fn queue(&self, conn: &PgConnection, room: Room) -> Result<Vec<Video>, ApiError> {
+        let now = SystemTime::now();
+
+        let new_videos: Vec<NewVideo> = self
+            .videos(conn)?
+            .into_iter()
+            .map(|video| NewVideo {
+                video_id: video.video_id,
+                title: video.title,
+                description: video.description,
+                room_id: room.id,
+                duration: video.duration,
+                added_on: now,
+                channel: video.channel,
+                added_by: Some(self.user_id),
+            })
+            .collect();
+
+        if new_videos.is_empty() {
+            let mut errors = ValidationErrors::new();
+            errors.add("videos", "The playlist is empty.");
+            return Err(errors.into());
+        }
+
+        YoutubeVideo::queue(conn, room, &new_videos)
+    }
+
+    fn insert_videos(conn: &PgConnection, p_id: i64, songs: Vec<Song>) -> Result<(), ApiError> {
+        use diesel::prelude::*;
+
+        let songs: Vec<NewSavedVideo> = songs
+            .into_iter()
+            .map(|song| NewSavedVideo {
+                playlist_id: p_id,
+                video_id: song.video_id,
+                title: song.title,
+                description: song.description,
+                duration: song.duration,
+                channel: song.channel,
+            })
+            .collect();
+
+        diesel::insert_into(saved_playlist_videos::table)
+            .values(&songs)
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+/// Trims the name and checks it isn't empty or too long
+pub fn check_name(name: &str) -> Result<String, ApiError> {
+    let name = name.trim();
+    let mut errors = ValidationErrors::new();
+
+    if name.is_empty() {
+        errors.add("name", "Can't be empty.");
+    } else if name.chars().count() > MAX_PLAYLIST_NAME_LENGTH {
+        errors.add(
+            "name",
+            &format!("Can't be longer than {} characters.", MAX_PLAYLIST_NAME_LENGTH),
+        );
+    }
+
+    errors.into_result()?;
+    Ok(name.to_string())
+}
+
+// Looks the songs up on YouTube, in the order they were given
+fn lookup(api_key: &str, ids: &[String]) -> Result<Vec<Song>, ApiError> {
+    if ids.len() > MAX_PLAYLIST_VIDEOS {
+        let mut errors = ValidationErrors::new();
+        errors.add(
+            "videos",
+            &format!("Can't hold more than {} songs.", MAX_PLAYLIST_VIDEOS),
+        );
+        return Err(errors.into());
+    }
+
+    let mut found = HashMap::new();
+
+    for chunk in ids.chunks(MAX_VIDEOS_PER_CALL) {
+        for details in YoutubeVideo::details(api_key, chunk)? {
+            found.insert(details.id.clone(), details);
+        }
+    }
+
+    let mut errors = ValidationErrors::new();
+    let mut songs = Vec::new();
+
+    for video_id in ids {
+        match found.get(video_id) {
+            Some(details) => songs.push(Song {
+                video_id: details.id.clone(),
+                title: details.snippet.title.clone(),
+                description: Some(details.snippet.description.clone()),
+                duration: details.contentDetails.duration.clone(),
+                channel: Some(details.snippet.channelTitle.clone()),
+            }),
+            None => errors.add("videos", &format!("YouTube doesn't know the video {}.", video_id)),
+        }
+    }
+
+    errors.into_result()?;
+    Ok(songs)
+}
+
+fn name_conflict(e: diesel::result::Error) -> ApiError {
+    use diesel::result::{DatabaseErrorKind, Error};
+
+    match e {
+        Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            let mut errors = ValidationErrors::new();
+            errors.add("name", "You already have a playlist with this name.");
+            errors.with_status(Status::Conflict).into()
+        }
+        e => e.into(),
+    }
+}
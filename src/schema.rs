@@ -1,9 +1,130 @@
+table! {
+    favourites (user_id, video_id) {
+        user_id -> Int8,
+        video_id -> Varchar,
+        title -> Varchar,
+        description -> Nullable<Varchar>,
+        duration -> Varchar,
+        room_id -> Nullable<Int8>,
+        added_on -> Timestamp,
+    }
+}
+
+table! {
+    pictures (key) {
+        key -> Varchar,
+        data -> Bytea,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    room_access_tokens (token) {
+        token -> Varchar,
+        room_id -> Int8,
+        created_on -> Timestamp,
+        expires_on -> Timestamp,
+    }
+}
+
+table! {
+    room_listeners (room_id, listener_id) {
+        room_id -> Int8,
+        listener_id -> Varchar,
+        user_id -> Nullable<Int8>,
+        joined_on -> Timestamp,
+        last_seen -> Timestamp,
+    }
+}
+
+table! {
+    room_members (room_id, user_id) {
+        room_id -> Int8,
+        user_id -> Int8,
+        joined_on -> Timestamp,
+    }
+}
+
+table! {
+    room_slugs (slug) {
+        slug -> Varchar,
+        room_id -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    room_settings (room_id) {
+        room_id -> Int8,
+        max_song_length -> Nullable<Int4>,
+        max_queue_per_user -> Nullable<Int4>,
+        skip_policy -> Varchar,
+        duplicate_policy -> Varchar,
+        fallback_mode -> Varchar,
+        allowed_sources -> Array<Varchar>,
+    }
+}
+
 table! {
     rooms (id) {
         id -> Int8,
         name -> Varchar,
         description -> Nullable<Varchar>,
         is_public -> Bool,
+        owner_id -> Nullable<Int8>,
+        invite_token -> Varchar,
+        password -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_activity_at -> Timestamp,
+        archived_at -> Nullable<Timestamp>,
+        deleted_at -> Nullable<Timestamp>,
+        slug -> Varchar,
+        name_skeleton -> Nullable<Varchar>,
+        picture_path -> Nullable<Varchar>,
+        picture_type -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    saved_playlist_videos (id) {
+        id -> Int8,
+        playlist_id -> Int8,
+        video_id -> Varchar,
+        title -> Varchar,
+        description -> Nullable<Varchar>,
+        duration -> Varchar,
+        channel -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    saved_playlists (id) {
+        id -> Int8,
+        user_id -> Int8,
+        name -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    sessions (token) {
+        token -> Varchar,
+        user_id -> Int8,
+        created_on -> Timestamp,
+        expires_on -> Timestamp,
+    }
+}
+
+table! {
+    trending_videos (video_id) {
+        video_id -> Varchar,
+        title -> Varchar,
+        channel -> Nullable<Varchar>,
+        queued -> Int8,
+        likes -> Int8,
+        rooms -> Int8,
+        computed_at -> Timestamp,
     }
 }
 
@@ -14,6 +135,8 @@ table! {
         password -> Varchar,
         added_on -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        is_admin -> Bool,
+        username_skeleton -> Nullable<Varchar>,
     }
 }
 
@@ -28,9 +151,41 @@ table! {
         played -> Bool,
         added_on -> Timestamp,
         started_on -> Nullable<Timestamp>,
+        channel -> Nullable<Varchar>,
+        added_by -> Nullable<Int8>,
+        skipped_on -> Nullable<Timestamp>,
     }
 }
 
+joinable!(favourites -> rooms (room_id));
+joinable!(favourites -> users (user_id));
+joinable!(room_access_tokens -> rooms (room_id));
+joinable!(room_listeners -> rooms (room_id));
+joinable!(room_listeners -> users (user_id));
+joinable!(room_members -> rooms (room_id));
+joinable!(room_members -> users (user_id));
+joinable!(room_settings -> rooms (room_id));
+joinable!(room_slugs -> rooms (room_id));
+joinable!(rooms -> users (owner_id));
+joinable!(saved_playlist_videos -> saved_playlists (playlist_id));
+joinable!(saved_playlists -> users (user_id));
+joinable!(sessions -> users (user_id));
 joinable!(videos -> rooms (room_id));
+joinable!(videos -> users (added_by));
 
-allow_tables_to_appear_in_same_query!(rooms, users, videos,);
+allow_tables_to_appear_in_same_query!(
+    favourites,
+    pictures,
+    room_access_tokens,
+    room_listeners,
+    room_members,
+    room_settings,
+    room_slugs,
+    rooms,
+    saved_playlist_videos,
+    saved_playlists,
+    sessions,
+    trending_videos,
+    users,
+    videos,
+);
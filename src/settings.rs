@@ -0,0 +1,361 @@
+use diesel;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgConnection};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Varchar;
+use rocket::http::Status;
+use rocket::response::Failure;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::io::Write;
+
+use error::ApiError;
+use player::duration_to_seconds;
+use room::Room;
+use schema::room_settings;
+use validation::{check_range, nullable, ValidationErrors};
+use video::NewVideo;
+
+/// Every source a video can currently be queued from
+pub const SOURCES: &[&str] = &["youtube"];
+
+// Declares a settings enum that is stored as VARCHAR and serialized as its snake_case name
+macro_rules! setting_enum {
+    ($name:ident { $($variant:ident => $value:tt),+ }) => {
+        #[derive(Clone, Copy, Debug, PartialEq, AsExpression, FromSqlRow)]
+        #[sql_type = "Varchar"]
+        pub enum $name {
+            $($variant),+
+        }
+
+        impl $name {
+            pub const VALUES: &'static [&'static str] = &[$($value),+];
+
+            pub fn as_str(&self) -> &'static str {
+                match *self {
+                    $($name::$variant => $value),+
+                }
+            }
+
+            pub fn parse(value: &str) -> Option<$name> {
+                match value {
+                    $($value => Some($name::$variant),)+
+                    _ => None,
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl ToSql<Varchar, Pg> for $name {
+            fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+                ToSql::<Varchar, Pg>::to_sql(self.as_str(), out)
+            }
+        }
+
+        impl FromSql<Varchar, Pg> for $name {
+            fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+                let value = ::std::str::from_utf8(not_none!(bytes))?;
+
+                $name::parse(value).ok_or_else(|| {
+                    format!("Unrecognized {} value: {}", stringify!($name), value).into()
+                })
+            }
+        }
+    };
+}
+
+setting_enum!(SkipPolicy {
+    Anyone => "anyone",
+    Disabled => "disabled"
+});
+
+setting_enum!(DuplicatePolicy {
+    Allow => "allow",
+    RejectQueued => "reject_queued"
+});
+
+setting_enum!(FallbackMode {
+    Silence => "silence",
+    ReplayHistory => "replay_history"
+});
+
+#[derive(Clone, Serialize, Queryable, Insertable, Identifiable, AsChangeset)]
+#[table_name = "room_settings"]
+#[primary_key(room_id)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct RoomSettings {
+    pub room_id: i64,
+    /// Longest song that can be queued, in seconds
+    pub max_song_length: Option<i32>,
+    /// Most unplayed songs a single user can have in the queue
+    pub max_queue_per_user: Option<i32>,
+    pub skip_policy: SkipPolicy,
+    pub duplicate_policy: DuplicatePolicy,
+    /// What the player does once the queue runs dry
+    pub fallback_mode: FallbackMode,
+    pub allowed_sources: Vec<String>,
+}
+
+/// A partial settings update, every field is optional.
+/// Enums are taken as plain strings so they can be reported per field.
+#[derive(Deserialize, Default)]
+pub struct RoomSettingsPatch {
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_song_length: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_queue_per_user: Option<Option<i64>>,
+    pub skip_policy: Option<String>,
+    pub duplicate_policy: Option<String>,
+    pub fallback_mode: Option<String>,
+    pub allowed_sources: Option<Vec<String>>,
+}
+
+impl RoomSettings {
+    pub fn default_for(room_id: i64) -> RoomSettings {
+        RoomSettings {
+            room_id,
+            max_song_length: None,
+            max_queue_per_user: None,
+            skip_policy: SkipPolicy::Anyone,
+            duplicate_policy: DuplicatePolicy::Allow,
+            fallback_mode: FallbackMode::Silence,
+            allowed_sources: SOURCES.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    // Find the settings of a room, rooms without a settings row use the defaults
+    #[inline]
+    pub fn find(conn: &PgConnection, r_id: i64) -> Result<RoomSettings, Failure> {
+        use diesel::prelude::*;
+        use schema::room_settings::dsl::*;
+
+        let result = room_settings
+            .filter(room_id.eq(r_id))
+            .first::<RoomSettings>(conn)
+            .optional();
+
+        match result {
+            Ok(Some(settings)) => Ok(settings),
+            Ok(None) => Ok(RoomSettings::default_for(r_id)),
+            Err(e) => {
+                error!("Error while fetching the room settings: {}", e);
+                Err(Failure(Status::InternalServerError))
+            }
+        }
+    }
+
+    #[inline]
+    pub fn update(
+        conn: &PgConnection,
+        r_id: i64,
+        patch: &RoomSettingsPatch,
+    ) -> Result<RoomSettings, ApiError> {
+        use diesel::prelude::*;
+        use schema::room_settings::dsl::*;
+
+        if Room::find(conn, r_id).is_none() {
+            return Err(ApiError::NotFound);
+        }
+
+        let mut settings = RoomSettings::find(conn, r_id)?;
+        patch.apply(&mut settings)?;
+
+        let result = diesel::insert_into(room_settings)
+            .values(&settings)
+            .on_conflict(room_id)
+            .do_update()
+            .set(&settings)
+            .get_result(conn);
+
+        match result {
+            Ok(settings) => Ok(settings),
+            Err(e) => Err(ApiError::Database(e)),
+        }
+    }
+
+    /// Rejects a batch of videos that may not be queued in this room,
+    /// with an error per video that names the setting it breaks
+    pub fn check_new_videos(
+        &self,
+        conn: &PgConnection,
+        source: &str,
+        new_videos: &[NewVideo],
+    ) -> Result<(), ApiError> {
+        use diesel::prelude::*;
+        use schema::videos::dsl::*;
+
+        if !self.allowed_sources.iter().any(|allowed| allowed == source) {
+            let mut errors = ValidationErrors::new();
+            errors.add(
+                "videos",
+                &format!("Songs from {} aren't in this room's allowed_sources.", source),
+            );
+            return Err(errors.with_status(Status::Forbidden).into());
+        }
+
+        let mut errors = ValidationErrors::new();
+
+        if let Some(max_song_length) = self.max_song_length {
+            for video in new_videos {
+                let too_long = match duration_to_seconds(&video.duration) {
+                    Some(seconds) => seconds > max_song_length as u64,
+                    None => true,
+                };
+
+                if too_long {
+                    errors.add(
+                        "videos",
+                        &format!(
+                            "{} is longer than max_song_length ({} seconds).",
+                            video.video_id, max_song_length
+                        ),
+                    );
+                }
+            }
+        }
+
+        // Songs queued anonymously can't be counted per user, so a room with a limit only takes
+        // songs from logged in users
+        if let Some(max_queue_per_user) = self.max_queue_per_user {
+            if new_videos.iter().any(|video| video.added_by.is_none()) {
+                let mut errors = ValidationErrors::new();
+                errors.add(
+                    "videos",
+                    &format!(
+                        "Log in to add songs, this room has a max_queue_per_user ({} songs).",
+                        max_queue_per_user
+                    ),
+                );
+                return Err(errors.with_status(Status::Unauthorized).into());
+            }
+
+            let mut adding: HashMap<i64, Vec<&str>> = HashMap::new();
+
+            for video in new_videos {
+                if let Some(user) = video.added_by {
+                    adding.entry(user).or_insert_with(Vec::new).push(&video.video_id);
+                }
+            }
+
+            for (user, adding) in adding {
+                let queued = videos
+                    .filter(room_id.eq(self.room_id))
+                    .filter(played.eq(false))
+                    .filter(added_by.eq(user))
+                    .count()
+                    .get_result::<i64>(conn)?;
+
+                let allowed = (i64::from(max_queue_per_user) - queued).max(0) as usize;
+
+                for over in adding.iter().skip(allowed) {
+                    errors.add(
+                        "videos",
+                        &format!(
+                            "{} would exceed max_queue_per_user ({} songs).",
+                            over, max_queue_per_user
+                        ),
+                    );
+                }
+            }
+        }
+
+        errors.into_result()?;
+
+        if self.duplicate_policy == DuplicatePolicy::RejectQueued {
+            let mut queued = videos
+                .select(video_id)
+                .filter(room_id.eq(self.room_id))
+                .filter(played.eq(false))
+                .load::<String>(conn)?;
+
+            let mut errors = ValidationErrors::new();
+
+            for video in new_videos {
+                if queued.contains(&video.video_id) {
+                    errors.add(
+                        "videos",
+                        &format!(
+                            "{} is already queued and duplicate_policy is reject_queued.",
+                            video.video_id
+                        ),
+                    );
+                }
+                queued.push(video.video_id.clone());
+            }
+
+            errors.with_status(Status::Conflict).into_result()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RoomSettingsPatch {
+    /// Validates every field and only writes them to `settings` when all of them are valid
+    pub fn apply(&self, settings: &mut RoomSettings) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let mut updated = settings.clone();
+
+        if let Some(max_song_length) = self.max_song_length {
+            if let Some(seconds) = max_song_length {
+                check_range(&mut errors, "max_song_length", seconds, 1, 86_400);
+            }
+            updated.max_song_length = max_song_length.map(|seconds| seconds as i32);
+        }
+
+        if let Some(max_queue_per_user) = self.max_queue_per_user {
+            if let Some(count) = max_queue_per_user {
+                check_range(&mut errors, "max_queue_per_user", count, 1, 1_000);
+            }
+            updated.max_queue_per_user = max_queue_per_user.map(|count| count as i32);
+        }
+
+        if let Some(ref value) = self.skip_policy {
+            match SkipPolicy::parse(value) {
+                Some(policy) => updated.skip_policy = policy,
+                None => errors.add("skip_policy", &one_of(SkipPolicy::VALUES)),
+            }
+        }
+
+        if let Some(ref value) = self.duplicate_policy {
+            match DuplicatePolicy::parse(value) {
+                Some(policy) => updated.duplicate_policy = policy,
+                None => errors.add("duplicate_policy", &one_of(DuplicatePolicy::VALUES)),
+            }
+        }
+
+        if let Some(ref value) = self.fallback_mode {
+            match FallbackMode::parse(value) {
+                Some(mode) => updated.fallback_mode = mode,
+                None => errors.add("fallback_mode", &one_of(FallbackMode::VALUES)),
+            }
+        }
+
+        if let Some(ref sources) = self.allowed_sources {
+            if sources.is_empty() {
+                errors.add("allowed_sources", "At least one source is required.");
+            } else if sources.iter().any(|source| !SOURCES.contains(&&source[..])) {
+                errors.add("allowed_sources", &one_of(SOURCES));
+            }
+
+            let mut sources = sources.clone();
+            sources.sort();
+            sources.dedup();
+            updated.allowed_sources = sources;
+        }
+
+        errors.into_result()?;
+        *settings = updated;
+
+        Ok(())
+    }
+}
+
+fn one_of(values: &[&str]) -> String {
+    format!("Must be one of: {}.", values.join(", "))
+}
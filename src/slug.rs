@@ -0,0 +1,115 @@
+use diesel;
+use diesel::pg::PgConnection;
+use rocket::http::Status;
+use rocket::response::Failure;
+use std::time::SystemTime;
+
+use schema::room_slugs;
+
+/// A slug a room has or had, old slugs are kept so links survive renames
+#[derive(Serialize, Queryable)]
+pub struct RoomSlug {
+    pub slug: String,
+    pub room_id: i64,
+    pub created_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "room_slugs"]
+pub struct NewRoomSlug<'a> {
+    pub slug: &'a str,
+    pub room_id: i64,
+}
+
+/// Lowercases a name and replaces everything that isn't a letter or digit with dashes,
+/// eg: "Death_Metal" becomes "death-metal".
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+
+    for c in name.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+
+    if slug.is_empty() {
+        slug.push_str("room");
+    }
+
+    slug
+}
+
+impl RoomSlug {
+    // Point a slug at a room, a slug that used to belong to another room is taken over
+    #[inline]
+    pub fn record(conn: &PgConnection, r_id: i64, new_slug: &str) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::room_slugs::dsl::*;
+
+        let result = diesel::insert_into(room_slugs)
+            .values(&NewRoomSlug {
+                slug: new_slug,
+                room_id: r_id,
+            })
+            .on_conflict(slug)
+            .do_update()
+            .set((room_id.eq(r_id), created_at.eq(SystemTime::now())))
+            .execute(conn);
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error while saving the room slug: {}", e);
+                Err(Failure(Status::InternalServerError))
+            }
+        }
+    }
+
+    // The id of the room a slug belongs or used to belong to
+    #[inline]
+    pub fn room_id(conn: &PgConnection, old_slug: &str) -> Option<i64> {
+        use diesel::prelude::*;
+        use schema::room_slugs::dsl::*;
+
+        room_slugs
+            .select(room_id)
+            .filter(slug.eq(old_slug))
+            .first::<i64>(conn)
+            .ok()
+    }
+
+    /// Returns `base` when no other room currently uses it,
+    /// otherwise the first free `base-2`, `base-3`, ...
+    pub fn available(conn: &PgConnection, base: &str, except_room: Option<i64>) -> String {
+        use diesel::dsl::exists;
+        use diesel::prelude::*;
+        use schema::rooms::dsl::*;
+
+        let mut candidate = base.to_string();
+        let mut suffix = 1;
+
+        loop {
+            let taken = diesel::select(exists(
+                rooms
+                    .filter(slug.eq(&candidate))
+                    .filter(deleted_at.is_null())
+                    .filter(id.ne(except_room.unwrap_or(-1))),
+            ))
+            .get_result(conn)
+            .unwrap_or(false);
+
+            if !taken {
+                return candidate;
+            }
+
+            suffix += 1;
+            candidate = format!("{}-{}", base, suffix);
+        }
+    }
+}
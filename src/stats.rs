@@ -0,0 +1,266 @@
+use diesel::pg::PgConnection;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Nullable, Timestamp, VarChar};
+use rocket::request::{self, FormItems, FromRequest, Request};
+use rocket::Outcome;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use error::ApiError;
+use player::duration_to_seconds;
+use room::Room;
+use validation::{check_range, parse_number, ValidationErrors};
+
+const DEFAULT_DAYS: i64 = 7;
+const MAX_DAYS: i64 = 365;
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+/// The query string of the statistics, eg: ?days=7&limit=10 for a weekly top 10.
+/// Unknown parameters are ignored, so invite links keep working.
+#[derive(Default)]
+pub struct StatsQuery {
+    pub days: Option<String>,
+    pub limit: Option<String>,
+}
+
+/// What a room played during the last `days` days
+#[derive(Serialize)]
+pub struct RoomStats {
+    pub room_id: i64,
+    pub from: SystemTime,
+    pub to: SystemTime,
+    #[serde(flatten)]
+    pub listening: Listening,
+    pub top_songs: Vec<SongStats>,
+    pub top_channels: Vec<ChannelStats>,
+    pub contributors: Vec<ContributorStats>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Listening {
+    pub songs_played: usize,
+    pub songs_skipped: usize,
+    /// Between 0 and 1
+    pub skip_rate: f64,
+    /// In seconds, skipped songs only count until they were skipped
+    pub listening_time: u64,
+    /// Hours of the day (UTC) with the most songs started, the busiest first
+    pub busiest_hours: Vec<HourStats>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct HourStats {
+    pub hour: u8,
+    pub plays: usize,
+}
+
+#[derive(Serialize, QueryableByName)]
+pub struct SongStats {
+    #[sql_type = "VarChar"]
+    pub video_id: String,
+    #[sql_type = "VarChar"]
+    pub title: String,
+    #[sql_type = "Nullable<VarChar>"]
+    pub channel: Option<String>,
+    #[sql_type = "BigInt"]
+    pub plays: i64,
+}
+
+#[derive(Serialize, QueryableByName)]
+pub struct ChannelStats {
+    #[sql_type = "VarChar"]
+    pub channel: String,
+    #[sql_type = "BigInt"]
+    pub plays: i64,
+}
+
+/// Only logged in users are known, anonymous songs aren't attributed to anyone
+#[derive(Serialize, QueryableByName)]
+pub struct ContributorStats {
+    #[sql_type = "BigInt"]
+    pub user_id: i64,
+    #[sql_type = "VarChar"]
+    pub username: String,
+    #[sql_type = "BigInt"]
+    pub songs: i64,
+}
+
+/// A song that was played, as far as the statistics are concerned
+pub struct Play {
+    pub started_on: SystemTime,
+    pub skipped_on: Option<SystemTime>,
+    /// In seconds
+    pub duration: u64,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for StatsQuery {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<StatsQuery, ()> {
+        let mut query = StatsQuery::default();
+
+        for (key, value) in FormItems::from(request.uri().query().unwrap_or("")) {
+            let value = value.url_decode().ok();
+
+            match key.as_str() {
+                "days" => query.days = value,
+                "limit" => query.limit = value,
+                _ => {}
+            }
+        }
+
+        Outcome::Success(query)
+    }
+}
+
+impl StatsQuery {
+    /// Returns the amount of days and the length of the top lists
+    pub fn parse(&self) -> Result<(i64, i64), ApiError> {
+        let mut errors = ValidationErrors::new();
+
+        let days = parse_number(&mut errors, "days", self.days.as_ref(), DEFAULT_DAYS);
+        check_range(&mut errors, "days", days, 1, MAX_DAYS);
+
+        let limit = parse_number(&mut errors, "limit", self.limit.as_ref(), DEFAULT_LIMIT);
+        check_range(&mut errors, "limit", limit, 1, MAX_LIMIT);
+
+        errors.into_result()?;
+        Ok((days, limit))
+    }
+}
+
+impl RoomStats {
+    #[inline]
+    pub fn compute(conn: &PgConnection, room: &Room, days: i64, limit: i64) -> Result<RoomStats, ApiError> {
+        use diesel::prelude::*;
+        use schema::videos::dsl::*;
+
+        let to = SystemTime::now();
+        let from = to - Duration::from_secs(days as u64 * 24 * 60 * 60);
+
+        let plays: Vec<Play> = videos
+            .select((started_on, skipped_on, duration))
+            .filter(room_id.eq(room.id))
+            .filter(played.eq(true))
+            .filter(started_on.ge(from))
+            .filter(started_on.lt(to))
+            .load::<(Option<SystemTime>, Option<SystemTime>, String)>(conn)?
+            .into_iter()
+            .filter_map(|(started, skipped, length)| {
+                started.map(|started| Play {
+                    started_on: started,
+                    skipped_on: skipped,
+                    duration: duration_to_seconds(&length).unwrap_or(0),
+                })
+            })
+            .collect();
+
+        let top_songs = sql_query(
+            "SELECT video_id, max(title) AS title, max(channel) AS channel, count(*) AS plays \
+             FROM videos \
+             WHERE room_id = $1 AND played AND started_on >= $2 AND started_on < $3 \
+             GROUP BY video_id \
+             ORDER BY plays DESC, max(started_on) DESC \
+             LIMIT $4",
+        ).bind::<BigInt, _>(room.id)
+            .bind::<Timestamp, _>(from)
+            .bind::<Timestamp, _>(to)
+            .bind::<BigInt, _>(limit)
+            .load::<SongStats>(conn)?;
+
+        let top_channels = sql_query(
+            "SELECT channel, count(*) AS plays \
+             FROM videos \
+             WHERE room_id = $1 AND played AND channel IS NOT NULL \
+             AND started_on >= $2 AND started_on < $3 \
+             GROUP BY channel \
+             ORDER BY plays DESC, channel \
+             LIMIT $4",
+        ).bind::<BigInt, _>(room.id)
+            .bind::<Timestamp, _>(from)
+            .bind::<Timestamp, _>(to)
+            .bind::<BigInt, _>(limit)
+            .load::<ChannelStats>(conn)?;
+
+        // Contributors are counted by the songs they queued, whether those got played yet or not
+        let contributors = sql_query(
+            "SELECT users.id AS user_id, users.username, count(*) AS songs \
+             FROM videos JOIN users ON users.id = videos.added_by \
+             WHERE videos.room_id = $1 AND videos.added_on >= $2 AND videos.added_on < $3 \
+             GROUP BY users.id, users.username \
+             ORDER BY songs DESC, users.username \
+             LIMIT $4",
+        ).bind::<BigInt, _>(room.id)
+            .bind::<Timestamp, _>(from)
+            .bind::<Timestamp, _>(to)
+            .bind::<BigInt, _>(limit)
+            .load::<ContributorStats>(conn)?;
+
+        Ok(RoomStats {
+            room_id: room.id,
+            from,
+            to,
+            listening: Listening::summarize(&plays, limit as usize),
+            top_songs,
+            top_channels,
+            contributors,
+        })
+    }
+}
+
+impl Listening {
+    /// Totals of a list of plays, with at most `limit` busiest hours
+    pub fn summarize(plays: &[Play], limit: usize) -> Listening {
+        let mut hours: BTreeMap<u8, usize> = BTreeMap::new();
+        let mut listening_time: u64 = 0;
+        let mut songs_skipped = 0;
+
+        for play in plays {
+            let listened = match play.skipped_on {
+                Some(skipped_on) => {
+                    songs_skipped += 1;
+                    skipped_on
+                        .duration_since(play.started_on)
+                        .map(|listened| listened.as_secs().min(play.duration))
+                        .unwrap_or(0)
+                }
+                None => play.duration,
+            };
+            listening_time = listening_time.saturating_add(listened);
+
+            *hours.entry(hour_of_day(play.started_on)).or_insert(0) += 1;
+        }
+
+        let mut busiest_hours: Vec<HourStats> = hours
+            .into_iter()
+            .map(|(hour, plays)| HourStats { hour, plays })
+            .collect();
+        // Stable, so hours with as many plays stay in the order of the day
+        busiest_hours.sort_by(|a, b| b.plays.cmp(&a.plays));
+        busiest_hours.truncate(limit);
+
+        let skip_rate = if plays.is_empty() {
+            0.0
+        } else {
+            songs_skipped as f64 / plays.len() as f64
+        };
+
+        Listening {
+            songs_played: plays.len(),
+            songs_skipped,
+            skip_rate,
+            listening_time,
+            busiest_hours,
+        }
+    }
+}
+
+fn hour_of_day(time: SystemTime) -> u8 {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+
+    (seconds % (24 * 60 * 60) / (60 * 60)) as u8
+}
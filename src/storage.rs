@@ -0,0 +1,357 @@
+use chrono::Utc;
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use diesel;
+use reqwest::header::Headers;
+use reqwest::{self, Method, StatusCode, Url};
+use rocket::config::Config;
+use rocket::http::Status;
+use rocket::response::Failure;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use schema::pictures;
+use {Pool, PICTURES_DIR};
+
+/// Somewhere to keep room pictures and their thumbnails, addressed by key, eg: 42.png
+pub trait PictureStorage: Send + Sync {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), Failure>;
+    /// Ok(None) when there is nothing stored under the key
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Failure>;
+    /// Removing a key that doesn't exist is not an error
+    fn delete(&self, key: &str) -> Result<(), Failure>;
+}
+
+/// The configured picture storage, shared between the routes and the janitor
+#[derive(Clone)]
+pub struct Storage(pub Arc<PictureStorage>);
+
+impl Storage {
+    pub fn new<S: PictureStorage + 'static>(storage: S) -> Storage {
+        Storage(Arc::new(storage))
+    }
+
+    /// The storage picked through PICTURE_STORAGE in Rocket.toml.
+    /// Panics when a setting the storage needs is missing, like the other settings in Rocket.toml
+    pub fn from_config(config: &Config, pool: &Pool) -> Storage {
+        let required = |key: &str| {
+            config
+                .get_str(key)
+                .unwrap_or_else(|_| panic!("{} not set in Rocket.toml.", key))
+                .to_string()
+        };
+
+        match config.get_str("PICTURE_STORAGE").unwrap_or("local") {
+            "local" => Storage::new(LocalStorage::new(
+                config.get_str("PICTURES_ROOT").unwrap_or(*PICTURES_DIR),
+            )),
+            "s3" => Storage::new(S3Storage {
+                endpoint: required("S3_ENDPOINT"),
+                bucket: required("S3_BUCKET"),
+                region: config.get_str("S3_REGION").unwrap_or("us-east-1").to_string(),
+                access_key: required("S3_ACCESS_KEY"),
+                secret_key: required("S3_SECRET_KEY"),
+            }),
+            "database" => Storage::new(DatabaseStorage { pool: pool.clone() }),
+            _ => panic!("PICTURE_STORAGE should be either 'local', 's3' or 'database'."),
+        }
+    }
+}
+
+/// Stores pictures as files below a root directory
+pub struct LocalStorage {
+    pub root: PathBuf,
+}
+
+/// Stores pictures in a bucket of an S3 compatible service, like AWS S3 or MinIO
+pub struct S3Storage {
+    /// eg: https://s3.eu-west-1.amazonaws.com or http://localhost:9000
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+/// Stores pictures in the pictures table
+pub struct DatabaseStorage {
+    pub pool: Pool,
+}
+
+#[derive(Insertable)]
+#[table_name = "pictures"]
+struct NewPicture<'a> {
+    key: &'a str,
+    data: &'a [u8],
+}
+
+fn storage_error<E: ::std::fmt::Display>(action: &str, key: &str, e: E) -> Failure {
+    error!("Error while {} picture {}: {}", action, key, e);
+    Failure(Status::InternalServerError)
+}
+
+// Keeps the temporary files of concurrent uploads apart
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+impl LocalStorage {
+    pub fn new<P: Into<PathBuf>>(root: P) -> LocalStorage {
+        LocalStorage { root: root.into() }
+    }
+}
+
+impl PictureStorage for LocalStorage {
+    // The picture is written to a temporary file that is moved in place once it is complete,
+    // so a crash never leaves a half-written picture behind
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), Failure> {
+        fs::create_dir_all(&self.root).map_err(|e| storage_error("storing", key, e))?;
+
+        let temp_path = self.root.join(format!(
+            ".{}.{}-{}.tmp",
+            key,
+            process::id(),
+            TEMP_FILES.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let result = write_synced(&temp_path, data)
+            .and_then(|_| fs::rename(&temp_path, self.root.join(key)));
+
+        if let Err(e) = result {
+            let _res = fs::remove_file(&temp_path);
+            return Err(storage_error("storing", key, e));
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Failure> {
+        match fs::read(self.root.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(storage_error("reading", key, e)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Failure> {
+        match fs::remove_file(self.root.join(key)) {
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(storage_error("removing", key, e)),
+        }
+    }
+}
+
+// Only returns once the data actually reached the disk
+fn write_synced(path: &Path, data: &[u8]) -> ::std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+impl S3Storage {
+    // Path style URLs work with every S3 compatible service
+    fn url(&self, key: &str) -> Result<Url, Failure> {
+        let url = format!(
+            "{}/{}/{}",
+            self.endpoint.trim_right_matches('/'),
+            uri_encode(&self.bucket),
+            uri_encode(key)
+        );
+
+        Url::parse(&url).map_err(|e| storage_error("locating", key, e))
+    }
+
+    // Sends a request signed with AWS Signature Version 4
+    fn send(&self, method: Method, key: &str, body: &[u8]) -> Result<reqwest::Response, Failure> {
+        let url = self.url(key)?;
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(storage_error("locating", key, "missing host")),
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let payload_hash = sha256_hex(body);
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            SIGNED_HEADERS,
+            payload_hash
+        );
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+
+        let signing_key = [date.as_str(), self.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.secret_key).into_bytes(), |key, part| {
+                hmac_sha256(&key, part.as_bytes())
+            });
+
+        let signature = to_hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        let mut headers = Headers::new();
+        headers.set_raw("x-amz-date", amz_date);
+        headers.set_raw("x-amz-content-sha256", payload_hash);
+        headers.set_raw(
+            "Authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, SIGNED_HEADERS, signature
+            ),
+        );
+
+        reqwest::Client::new()
+            .request(method, url)
+            .headers(headers)
+            .body(body.to_vec())
+            .send()
+            .map_err(|e| storage_error("requesting", key, e))
+    }
+}
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+impl PictureStorage for S3Storage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), Failure> {
+        let response = self.send(Method::Put, key, data)?;
+
+        if !response.status().is_success() {
+            return Err(storage_error("storing", key, response.status()));
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Failure> {
+        let mut response = self.send(Method::Get, key, &[])?;
+
+        match response.status() {
+            StatusCode::NotFound => Ok(None),
+            status if status.is_success() => {
+                let mut data = Vec::new();
+                response
+                    .read_to_end(&mut data)
+                    .map_err(|e| storage_error("reading", key, e))?;
+                Ok(Some(data))
+            }
+            status => Err(storage_error("reading", key, status)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Failure> {
+        let response = self.send(Method::Delete, key, &[])?;
+
+        match response.status() {
+            StatusCode::NotFound => Ok(()),
+            status if status.is_success() => Ok(()),
+            status => Err(storage_error("removing", key, status)),
+        }
+    }
+}
+
+impl PictureStorage for DatabaseStorage {
+    fn put(&self, picture_key: &str, picture_data: &[u8]) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::pictures::dsl::*;
+
+        let conn = self
+            .pool
+            .get()
+            .map_err(|e| storage_error("storing", picture_key, e))?;
+
+        diesel::insert_into(pictures)
+            .values(&NewPicture {
+                key: picture_key,
+                data: picture_data,
+            })
+            .on_conflict(key)
+            .do_update()
+            .set(data.eq(picture_data))
+            .execute(&*conn)
+            .map(|_| ())
+            .map_err(|e| storage_error("storing", picture_key, e))
+    }
+
+    fn get(&self, picture_key: &str) -> Result<Option<Vec<u8>>, Failure> {
+        use diesel::prelude::*;
+        use schema::pictures::dsl::*;
+
+        let conn = self
+            .pool
+            .get()
+            .map_err(|e| storage_error("reading", picture_key, e))?;
+
+        pictures
+            .select(data)
+            .filter(key.eq(picture_key))
+            .first::<Vec<u8>>(&*conn)
+            .optional()
+            .map_err(|e| storage_error("reading", picture_key, e))
+    }
+
+    fn delete(&self, picture_key: &str) -> Result<(), Failure> {
+        use diesel::prelude::*;
+        use schema::pictures::dsl::*;
+
+        let conn = self
+            .pool
+            .get()
+            .map_err(|e| storage_error("removing", picture_key, e))?;
+
+        diesel::delete(pictures.filter(key.eq(picture_key)))
+            .execute(&*conn)
+            .map(|_| ())
+            .map_err(|e| storage_error("removing", picture_key, e))
+    }
+}
+
+// Percent encodes everything but unreserved characters and slashes, as S3 expects
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result_str()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
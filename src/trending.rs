@@ -0,0 +1,188 @@
+use diesel::pg::PgConnection;
+use diesel::sql_query;
+use diesel::sql_types::Timestamp;
+use log::Level;
+use rocket::config::Config;
+use std::time::{Duration, SystemTime};
+use std::{thread, time};
+
+use error::ApiError;
+use establish_connection;
+use validation::{check_range, parse_number, ValidationErrors};
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+/// Configured through TRENDING_WINDOW_HOURS and TRENDING_INTERVAL_MINUTES in Rocket.toml
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrendingConfig {
+    /// Only songs queued or liked during the last this many hours count
+    pub window_hours: u64,
+    /// How often the trending songs are computed again
+    pub interval_minutes: u64,
+}
+
+impl Default for TrendingConfig {
+    fn default() -> TrendingConfig {
+        TrendingConfig {
+            window_hours: 24,
+            interval_minutes: 5,
+        }
+    }
+}
+
+impl TrendingConfig {
+    pub fn from_config(config: &Config) -> TrendingConfig {
+        let defaults = TrendingConfig::default();
+
+        TrendingConfig {
+            window_hours: config
+                .get_int("TRENDING_WINDOW_HOURS")
+                .map(|hours| hours.max(1) as u64)
+                .unwrap_or(defaults.window_hours),
+            interval_minutes: config
+                .get_int("TRENDING_INTERVAL_MINUTES")
+                .map(|minutes| minutes.max(1) as u64)
+                .unwrap_or(defaults.interval_minutes),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_hours * 60 * 60)
+    }
+}
+
+/// The query string of /trending, eg: /trending?limit=20.
+/// The limit is taken as a string, so an invalid one is reported instead of ignored.
+#[derive(FromForm, Default)]
+pub struct TrendingQuery {
+    pub limit: Option<String>,
+}
+
+impl TrendingQuery {
+    /// Returns the length of the lists
+    pub fn parse(&self) -> Result<i64, ApiError> {
+        let mut errors = ValidationErrors::new();
+
+        let limit = parse_number(&mut errors, "limit", self.limit.as_ref(), DEFAULT_LIMIT);
+        check_range(&mut errors, "limit", limit, 1, MAX_LIMIT);
+
+        errors.into_result()?;
+        Ok(limit)
+    }
+}
+
+/// A song as it was counted by the last run of the trending job
+#[derive(Serialize, Queryable)]
+pub struct TrendingVideo {
+    pub video_id: String,
+    pub title: String,
+    pub channel: Option<String>,
+    /// How often it was queued in a public room
+    pub queued: i64,
+    /// How often it was added to someone's favourites while playing in a public room
+    pub likes: i64,
+    /// In how many public rooms it was queued
+    pub rooms: i64,
+    #[serde(skip_serializing)]
+    pub computed_at: SystemTime,
+}
+
+#[derive(Serialize)]
+pub struct Trending {
+    /// None until the trending job ran for the first time
+    pub computed_at: Option<SystemTime>,
+    pub window_hours: u64,
+    pub most_queued: Vec<TrendingVideo>,
+    pub most_liked: Vec<TrendingVideo>,
+}
+
+impl Trending {
+    /// Reads what the trending job computed last, this never touches the videos table
+    #[inline]
+    pub fn get(conn: &PgConnection, config: &TrendingConfig, query: &TrendingQuery) -> Result<Trending, ApiError> {
+        use diesel::prelude::*;
+        use schema::trending_videos::dsl::*;
+
+        let limit = query.parse()?;
+
+        let most_queued = trending_videos
+            .filter(queued.gt(0))
+            .order((queued.desc(), rooms.desc(), likes.desc(), video_id))
+            .limit(limit)
+            .load::<TrendingVideo>(conn)?;
+
+        let most_liked = trending_videos
+            .filter(likes.gt(0))
+            .order((likes.desc(), queued.desc(), video_id))
+            .limit(limit)
+            .load::<TrendingVideo>(conn)?;
+
+        let computed = trending_videos
+            .select(computed_at)
+            .first::<SystemTime>(conn)
+            .optional()?;
+
+        Ok(Trending {
+            computed_at: computed,
+            window_hours: config.window_hours,
+            most_queued,
+            most_liked,
+        })
+    }
+}
+
+/// Counts what was queued and liked in the public rooms during the window and replaces
+/// the previous counts. Returns the amount of trending songs.
+pub fn refresh_trending(conn: &PgConnection, config: &TrendingConfig) -> Result<usize, ApiError> {
+    use diesel::prelude::*;
+
+    let now = SystemTime::now();
+    let since = now - config.window();
+
+    conn.transaction(|| {
+        sql_query("DELETE FROM trending_videos").execute(conn)?;
+
+        sql_query(
+            "WITH public_rooms AS ( \
+                 SELECT id FROM rooms \
+                 WHERE is_public AND archived_at IS NULL AND deleted_at IS NULL \
+             ), queued AS ( \
+                 SELECT video_id, max(title) AS title, max(channel) AS channel, \
+                     count(*) AS queued, count(DISTINCT room_id) AS rooms \
+                 FROM videos \
+                 WHERE room_id IN (SELECT id FROM public_rooms) AND added_on >= $1 \
+                 GROUP BY video_id \
+             ), liked AS ( \
+                 SELECT video_id, max(title) AS title, count(*) AS likes \
+                 FROM favourites \
+                 WHERE room_id IN (SELECT id FROM public_rooms) AND added_on >= $1 \
+                 GROUP BY video_id \
+             ) \
+             INSERT INTO trending_videos (video_id, title, channel, queued, likes, rooms, computed_at) \
+             SELECT coalesce(queued.video_id, liked.video_id), coalesce(queued.title, liked.title), \
+                 queued.channel, coalesce(queued.queued, 0), coalesce(liked.likes, 0), \
+                 coalesce(queued.rooms, 0), $2 \
+             FROM queued FULL OUTER JOIN liked ON liked.video_id = queued.video_id",
+        ).bind::<Timestamp, _>(since)
+            .bind::<Timestamp, _>(now)
+            .execute(conn)
+    }).map_err(Into::into)
+}
+
+/// Start a thread that periodically computes the trending songs
+pub fn start_trending(config: TrendingConfig) {
+    thread::Builder::new()
+        .spawn(move || {
+            let conn: PgConnection = establish_connection();
+
+            loop {
+                match refresh_trending(&conn, &config) {
+                    Ok(songs) => event!(Level::Debug, "Computed the trending songs"; "songs" => songs),
+                    Err(e) => event!(Level::Error, "Unable to compute the trending songs"; "error" => e.to_string()),
+                }
+                thread::sleep(time::Duration::from_secs(config.interval_minutes * 60));
+            }
+        })
+        .unwrap();
+}
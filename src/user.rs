@@ -2,21 +2,27 @@ use diesel;
 use diesel::prelude::*;
 use diesel::pg::PgConnection;
 use lower;
-use regex::Regex;
 use rocket::http::Status;
 use rocket::response::Failure;
 use bcrypt::{hash, verify, DEFAULT_COST};
 use std::time::SystemTime;
 
+use error::ApiError;
+use names::{normalize, skeleton, NameRules};
 use schema::users;
+use validation::ValidationErrors;
 
-#[derive(Queryable, Identifiable)]
+#[derive(Serialize, Queryable, Identifiable)]
 pub struct User {
     pub id: i64,
     pub username: String,
+    #[serde(skip_serializing)]
     pub password_hash: String,
     pub created_at: SystemTime,
     pub updated_at: Option<SystemTime>,
+    pub is_admin: bool,
+    #[serde(skip_serializing)]
+    pub username_skeleton: Option<String>,
 }
 
 #[derive(Insertable, Deserialize)]
@@ -24,35 +30,53 @@ pub struct User {
 pub struct NewUser {
     pub username: String,
     pub password: String,
+    #[serde(skip_deserializing)]
+    pub username_skeleton: Option<String>,
 }
 
 impl User {
-    pub fn verify_name(name: &str) -> Result<String, String> {
-        let name = name.trim();
-
-        if name.is_empty() {
-            return Err(String::from("Name cannot be empty."));
-        }
-
-        let regex = Regex::new(r"^[[:word:]]{3,20}$").unwrap();
+    // Returns the normalized name or a message explaining what's wrong with it
+    pub fn verify_name(rules: &NameRules, name: &str) -> Result<String, String> {
+        rules.check(name)
+    }
 
-        if !regex.is_match(name) {
-            return Err(String::from("Invalid username."));
+    // Returns a message explaining what's wrong with the password
+    pub fn verify_password(password: &str) -> Result<(), String> {
+        if password.len() < 8 {
+            return Err("Must be at least 8 characters long.".to_string());
         }
 
-        Ok(name.to_string())
+        Ok(())
     }
 
-    pub fn create(conn: &PgConnection, mut new_user: NewUser) -> Result<User, Failure> {
+    pub fn create(conn: &PgConnection, mut new_user: NewUser) -> Result<User, ApiError> {
+        use diesel::dsl::exists;
         use diesel::prelude::*;
+        use diesel::result::{DatabaseErrorKind, Error};
+        use schema::users::dsl::username_skeleton;
+
+        let name_skeleton = skeleton(&new_user.username);
+
+        // Look-alike names are rejected without telling which user they resemble
+        let look_alike = diesel::select(exists(
+            users::table.filter(username_skeleton.eq(&name_skeleton)),
+        ))
+        .get_result(conn)
+        .unwrap_or(false);
+
+        if look_alike {
+            return Err(username_taken().into());
+        }
+
+        new_user.username_skeleton = Some(name_skeleton);
 
         match hash(&new_user.password[..], DEFAULT_COST) {
             Ok(hashed) => {
                 new_user.password = hashed;
             }
             Err(e) => {
-                println!("Errow while hasing a password: {}", e);
-                return Err(Failure(Status::InternalServerError));
+                error!("Error while hashing a password: {}", e);
+                return Err(Failure(Status::InternalServerError).into());
             }
         };
 
@@ -62,25 +86,29 @@ impl User {
 
         match result {
             Ok(result) => Ok(result),
-            Err(_) => Err(Failure(Status::InternalServerError)),
+            Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(username_taken().into())
+            }
+            Err(e) => Err(ApiError::Database(e)),
         }
     }
 
-    // Verifies the user's password
-    pub fn authenticate(conn: &PgConnection, user: &User) -> Result<bool, Failure> {
+    // Returns the user when the password matches
+    pub fn authenticate(conn: &PgConnection, name: &str, pass: &str) -> Result<User, Failure> {
         use schema::users::dsl::*;
 
         let result = users
-            .filter(lower(username).eq(user.username.to_lowercase()))
+            .filter(lower(username).eq(normalize(name).to_lowercase()))
             .first::<User>(conn);
 
-        if result.is_err() {
-            return Err(Failure(Status::InternalServerError));
-        }
+        let user = match result {
+            Ok(user) => user,
+            Err(_) => return Err(Failure(Status::Unauthorized)),
+        };
 
-        match verify(&result.unwrap().password_hash[..], &user.password_hash[..]) {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
+        match verify(pass, &user.password_hash[..]) {
+            Ok(true) => Ok(user),
+            _ => Err(Failure(Status::Unauthorized)),
         }
     }
 
@@ -94,12 +122,34 @@ impl User {
         match result {
             Ok(result) => Ok(result),
             Err(e) => {
-                println!("Could not find user with id: {}", e);
+                warn!("Could not find user with id {}: {}", user_id, e);
                 Err(Failure(Status::NotFound))
             }
         }
     }
 
+    // Find a user by name, regardless of case
+    pub fn find_by_name(conn: &PgConnection, name: &str) -> Option<User> {
+        use diesel::prelude::*;
+        use schema::users::dsl::*;
+
+        users
+            .filter(lower(username).eq(normalize(name).to_lowercase()))
+            .first::<User>(conn)
+            .ok()
+    }
+
+    // Grant or revoke admin rights
+    pub fn set_admin(conn: &PgConnection, user_id: i64, admin: bool) -> Result<User, ApiError> {
+        use diesel::prelude::*;
+        use schema::users::dsl::*;
+
+        diesel::update(users.filter(id.eq(user_id)))
+            .set((is_admin.eq(admin), updated_at.eq(SystemTime::now())))
+            .get_result::<User>(conn)
+            .map_err(Into::into)
+    }
+
     // Return all users
     pub fn all(conn: &PgConnection) -> Result<Vec<User>, Failure> {
         use diesel::prelude::*;
@@ -110,9 +160,15 @@ impl User {
         match result {
             Ok(result) => Ok(result),
             Err(e) => {
-                println!("Error while fetching the users: {}", e);
+                error!("Error while fetching the users: {}", e);
                 Err(Failure(Status::InternalServerError))
             }
         }
     }
 }
+
+fn username_taken() -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add("username", "Is already taken or looks too much like another username.");
+    errors.with_status(Status::Conflict)
+}
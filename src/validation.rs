@@ -0,0 +1,100 @@
+use rocket::http::Status;
+use serde::{Deserialize, Deserializer};
+
+#[derive(Serialize, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+// Rejected fields, answered with a 422 through ApiError::Validation
+#[derive(Serialize, Debug)]
+pub struct ValidationErrors {
+    pub status: u16,
+    pub message: String,
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> ValidationErrors {
+        ValidationErrors {
+            status: 422,
+            message: "Validation failed".to_string(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, field: &str, message: &str) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Answer with another status than 422, eg: a 409 for names that are already taken
+    pub fn with_status(mut self, status: Status) -> ValidationErrors {
+        self.status = status.code;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Ok(()) when nothing was rejected, the collected errors otherwise
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl Default for ValidationErrors {
+    fn default() -> ValidationErrors {
+        ValidationErrors::new()
+    }
+}
+
+/// Serde helper that tells a missing field (None) apart from an explicit null (Some(None)),
+/// use it together with `#[serde(default)]`.
+pub fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+/// Adds an error for `field` when `value` falls outside of `min..=max`
+pub fn check_range(errors: &mut ValidationErrors, field: &str, value: i64, min: i64, max: i64) {
+    if value < min || value > max {
+        errors.add(field, &format!("Must be between {} and {}.", min, max));
+    }
+}
+
+/// Parses a query string number, adds an error for `field` when it isn't one.
+/// Returns `default` when the value is missing or invalid.
+pub fn parse_number(errors: &mut ValidationErrors, field: &str, value: Option<&String>, default: i64) -> i64 {
+    match value {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            errors.add(field, "Must be a number.");
+            default
+        }),
+        None => default,
+    }
+}
+
+/// Parses a query string flag, adds an error for `field` when it isn't true or false
+pub fn parse_bool(errors: &mut ValidationErrors, field: &str, value: Option<&String>) -> Option<bool> {
+    match value.map(|v| &v[..]) {
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        Some(_) => {
+            errors.add(field, "Must be true or false.");
+            None
+        }
+        None => None,
+    }
+}
@@ -1,4 +1,7 @@
+use diesel;
+use diesel::pg::PgConnection;
 use super::schema::videos;
+use error::ApiError;
 use room::Room;
 use std::time::SystemTime;
 
@@ -15,6 +18,26 @@ pub struct Video {
     pub played: bool,
     pub added_on: SystemTime,
     pub started_on: Option<SystemTime>,
+    /// The YouTube channel, unknown for songs that were added before it was kept
+    pub channel: Option<String>,
+    /// The user who queued it, None for anonymous users and songs replayed from the history
+    pub added_by: Option<i64>,
+    /// Set when the song was skipped before it ended
+    pub skipped_on: Option<SystemTime>,
+}
+
+impl Video {
+    /// Removes the played videos that started before `before` from every room's history.
+    /// Returns the amount of removed videos.
+    #[inline]
+    pub fn purge_played(conn: &PgConnection, before: SystemTime) -> Result<usize, ApiError> {
+        use diesel::prelude::*;
+        use schema::videos::dsl::*;
+
+        diesel::delete(videos.filter(played.eq(true)).filter(started_on.lt(before)))
+            .execute(conn)
+            .map_err(Into::into)
+    }
 }
 
 #[derive(Insertable, Serialize)]
@@ -26,4 +49,6 @@ pub struct NewVideo {
     pub room_id: i64,
     pub duration: String,
     pub added_on: SystemTime,
+    pub channel: Option<String>,
+    pub added_by: Option<i64>,
 }
@@ -2,13 +2,25 @@ use diesel::pg::PgConnection;
 
 use video::{NewVideo, Video};
 use room::Room;
-use rocket::http::Status;
-use rocket::response::Failure;
+use settings::RoomSettings;
+use error::ApiError;
+use metrics::{self, YoutubeCall};
 use std::time::SystemTime;
 use std::io::Read;
 
 pub struct ApiKey(pub String);
 
+/// The videos api answers with at most 50 videos per call
+pub const MAX_VIDEOS_PER_CALL: usize = 50;
+
+/// Whether `id` looks like a YouTube video id: 11 letters, digits, dashes or underscores
+pub fn is_video_id(id: &str) -> bool {
+    id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
 #[derive(FromForm)]
 pub struct YoutubeQuery {
     pub query: String,
@@ -127,65 +139,33 @@ pub struct YoutubeVideosDetailed {
 impl YoutubeVideo {
     /// Returns a list of videos from Youtube
     #[inline]
-    pub fn search(api_key: &str, query: &str) -> Result<String, Failure> {
-        use reqwest;
-
+    pub fn search(api_key: &str, query: &str) -> Result<String, ApiError> {
         let url = format!(
             "{}/search?type=video&part=id,snippet&maxResults=20&key={}&q={}&videoCategoryId=10",
             *super::API_URL,
             api_key,
             query
         );
-        let resp = reqwest::get(&url);
 
-        match resp {
-            Ok(mut resp) => {
-                let mut content = String::new();
-                resp.read_to_string(&mut content).unwrap();
-                YoutubeVideo::get_video_durations(api_key, Some(&content))
-            }
-            Err(_) => Err(Failure(Status::InternalServerError)),
-        }
+        let content = fetch(YoutubeCall::Search, &url)?;
+        YoutubeVideo::get_video_durations(api_key, &content)
     }
 
     // Fetches the duration from Youtube for a list of videos
-    fn get_video_durations(api_key: &str, json_videos: Option<&String>) -> Result<String, Failure> {
+    fn get_video_durations(api_key: &str, json_videos: &str) -> Result<String, ApiError> {
         use serde_json;
-        use reqwest;
 
-        let videos;
         let mut url: String = format!("{}/videos?id=", *super::API_URL).to_string();
 
-        match json_videos {
-            Some(json_videos) => {
-                videos = Some(json_videos).unwrap();
-            }
-            None => return Err(Failure(Status::InternalServerError)),
-        }
-
-        let result = serde_json::from_str(videos);
-        let videos: YoutubeVideos;
-
-        match result {
-            Ok(result) => videos = result,
-            Err(_) => return Err(Failure(Status::InternalServerError)),
-        }
+        let videos: YoutubeVideos = serde_json::from_str(json_videos)
+            .map_err(|e| ApiError::Youtube(format!("Unexpected search result: {}", e)))?;
 
         for youtube_video in &videos.items {
             url = format!("{},{}", url, youtube_video.id.videoId);
         }
 
         url = format!("{}&part=id,snippet,contentDetails&key={}", url, api_key);
-        let resp = reqwest::get(&url);
-
-        match resp {
-            Ok(mut resp) => {
-                let mut content = String::new();
-                resp.read_to_string(&mut content).unwrap();
-                Ok(content)
-            }
-            Err(_) => Err(Failure(Status::InternalServerError)),
-        }
+        fetch(YoutubeCall::Videos, &url)
     }
     // Takes a string of youtube video id's seperated by a comma
     // eg: ssxNqBPRL6Y,_wy4tuFEpz0,...
@@ -195,70 +175,133 @@ impl YoutubeVideo {
         conn: &PgConnection,
         video_id: &[String],
         room_id: i64,
-    ) -> Result<Vec<Video>, Failure> {
+        added_by: Option<i64>,
+    ) -> Result<Vec<Video>, ApiError> {
+        let mut videos: Vec<NewVideo> = Vec::new();
+
+        let room = match Room::find(conn, room_id) {
+            Some(room) => room,
+            None => return Err(ApiError::NotFound),
+        };
+
+        for youtube_video in &YoutubeVideo::details(api_key, video_id)? {
+            let new_video = NewVideo {
+                video_id: youtube_video.id.to_string(),
+                title: youtube_video.snippet.title.to_string(),
+                description: Some(youtube_video.snippet.description.to_string()),
+                room_id: room.id,
+                duration: youtube_video.contentDetails.duration.to_string(),
+                added_on: SystemTime::now(),
+                channel: Some(youtube_video.snippet.channelTitle.to_string()),
+                added_by,
+            };
+
+            videos.push(new_video);
+        }
+
+        YoutubeVideo::queue(conn, room, &videos)
+    }
+
+    /// Checks new videos against the settings of the room, adds them to the queue and starts playing.
+    /// Every song that gets added to a room goes through here.
+    pub fn queue(conn: &PgConnection, room: Room, videos: &[NewVideo]) -> Result<Vec<Video>, ApiError> {
         use schema::videos;
-        use reqwest;
-        use serde_json;
         use diesel;
         use diesel::RunQueryDsl;
         use player;
 
-        let mut videos: Vec<NewVideo> = Vec::new();
-        let id_list = video_id.join(",");
+        RoomSettings::find(conn, room.id)?.check_new_videos(conn, "youtube", videos)?;
 
-        let room = Room::find(conn, room_id);
+        let result = diesel::insert_into(videos::table)
+            .values(videos)
+            .get_results(conn)?;
+
+        Room::touch(conn, room.id);
+        player::start_playing(room);
+        Ok(result)
+    }
+
+    /// Fetches the title, description, duration and channel of the stored videos again,
+    /// of every room or only of `r_id`. Videos YouTube no longer knows are left alone.
+    /// Returns the amount of updated videos.
+    pub fn refresh(api_key: &str, conn: &PgConnection, r_id: Option<i64>) -> Result<usize, ApiError> {
+        use diesel;
+        use diesel::prelude::*;
+        use schema::videos::dsl::*;
 
-        if room.is_none() {
-            return Err(Failure(Status::NotFound));
+        let mut query = videos.select(video_id).distinct().into_boxed();
+
+        if let Some(r_id) = r_id {
+            query = query.filter(room_id.eq(r_id));
         }
 
-        let room = room.unwrap();
+        let ids = query.load::<String>(conn)?;
+        let mut updated = 0;
+
+        for chunk in ids.chunks(MAX_VIDEOS_PER_CALL) {
+            for details in YoutubeVideo::details(api_key, chunk)? {
+                let mut target = diesel::update(videos)
+                    .filter(video_id.eq(&details.id))
+                    .into_boxed();
+
+                if let Some(r_id) = r_id {
+                    target = target.filter(room_id.eq(r_id));
+                }
+
+                updated += target
+                    .set((
+                        title.eq(&details.snippet.title),
+                        description.eq(Some(&details.snippet.description)),
+                        duration.eq(&details.contentDetails.duration),
+                        channel.eq(Some(&details.snippet.channelTitle)),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// Fetches the snippet and content details of at most MAX_VIDEOS_PER_CALL videos.
+    /// Videos YouTube doesn't know are left out.
+    pub fn details(api_key: &str, video_id: &[String]) -> Result<Vec<YoutubeVideoDetailed>, ApiError> {
+        use serde_json;
 
         let url = format!(
             "{}/videos?id={}&part=id,snippet,contentDetails&key={}",
             *super::API_URL,
-            id_list,
+            video_id.join(","),
             api_key
         );
 
-        let resp = reqwest::get(&url);
-        let mut content = String::new();
+        let content = fetch(YoutubeCall::Videos, &url)?;
 
-        match resp {
-            Ok(mut resp) => {
-                resp.read_to_string(&mut content).unwrap();
-            }
-            Err(_) => return Err(Failure(Status::InternalServerError)),
-        }
+        let result: YoutubeVideosDetailed = serde_json::from_str(&content)
+            .map_err(|e| ApiError::Youtube(format!("Unexpected video details: {}", e)))?;
 
-        let result: YoutubeVideosDetailed = serde_json::from_str(&content).unwrap();
+        Ok(result.items)
+    }
+}
 
-        for youtube_video in &result.items {
-            let new_video = NewVideo {
-                video_id: youtube_video.id.to_string(),
-                title: youtube_video.snippet.title.to_string(),
-                description: Some(youtube_video.snippet.description.to_string()),
-                room_id: room.id,
-                duration: youtube_video.contentDetails.duration.to_string(),
-                added_on: SystemTime::now(),
-            };
+// Errors, like an exceeded quota, are answered with an error status
+fn fetch(call: YoutubeCall, url: &str) -> Result<String, ApiError> {
+    let result = fetch_unrecorded(url);
+    metrics::record(|metrics| metrics.youtube_call(call, result.is_ok()));
+    result
+}
 
-            videos.push(new_video);
-        }
+fn fetch_unrecorded(url: &str) -> Result<String, ApiError> {
+    use reqwest;
 
-        let result = diesel::insert_into(videos::table)
-            .values(&videos)
-            .get_results(conn);
+    let mut resp = reqwest::get(url)?;
 
-        match result {
-            Ok(result) => {
-                player::start_playing(room);
-                Ok(result)
-            }
-            Err(e) => {
-                println!("{}", e);
-                Err(Failure(Status::InternalServerError))
-            }
-        }
+    if !resp.status().is_success() {
+        return Err(ApiError::Youtube(format!("YouTube answered with {}", resp.status())));
     }
+
+    let mut content = String::new();
+    resp.read_to_string(&mut content)
+        .map_err(|e| ApiError::Youtube(e.to_string()))?;
+
+    Ok(content)
 }
@@ -1,8 +1,791 @@
+extern crate image;
+extern crate rocket;
+#[macro_use]
+extern crate serde_json;
 extern crate youkebox;
 
+use image::{DynamicImage, GenericImage, ImageFormat};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use std::{env, fs, thread};
+use rocket::config::{Config, Environment};
+use rocket::http::Status;
+use rocket::response::Failure;
+use youkebox::error::ApiError;
+use youkebox::export::{RoomImport, EXPORT_VERSION, MAX_IMPORT_SIZE};
+use youkebox::favourite::{ExportedPlaylist, Favourite, YoutubePlaylists};
+use youkebox::http::HttpStatus;
+use youkebox::listener::ListenerConfig;
+use youkebox::logging::{redact_uri, LogFormat};
+use youkebox::health::{check_player, Check, Readiness};
+use youkebox::metrics::{Metrics, YoutubeCall};
+use youkebox::migrations::MIGRATIONS;
+use youkebox::names::{skeleton, NameRules};
+use youkebox::picture::{self, MAX_UPLOAD_SIZE, PICTURE_SIZE, THUMBNAIL_SIZES};
 use youkebox::player::duration_to_seconds;
+use youkebox::playlist_file::{PlaylistEntry, PlaylistFormat, PlaylistList, PlaylistQuery};
+use youkebox::room::{RoomPatch, RoomQuery, RoomSort};
+use youkebox::saved_playlist::{self, MAX_PLAYLIST_NAME_LENGTH};
+use youkebox::settings::{RoomSettings, RoomSettingsPatch, SkipPolicy};
+use youkebox::slug::slugify;
+use youkebox::stats::{HourStats, Listening, Play, StatsQuery};
+use youkebox::storage::{LocalStorage, PictureStorage, S3Storage};
+use youkebox::trending::{TrendingConfig, TrendingQuery};
+use youkebox::validation::ValidationErrors;
 
 #[test]
 fn parse_duration() {
-    assert_eq!(4210, duration_to_seconds(&"PT1H10M10S".to_string()));
+    assert_eq!(Some(4210), duration_to_seconds("PT1H10M10S"));
+    assert_eq!(Some(180), duration_to_seconds("PT3M"));
+    assert_eq!(Some(90_061), duration_to_seconds("P1DT1H1M1S"));
+    assert_eq!(Some(0), duration_to_seconds("P0D"));
+    assert_eq!(Some(99_999_999_999), duration_to_seconds("PT99999999999S"));
+
+    assert_eq!(None, duration_to_seconds(""));
+    assert_eq!(None, duration_to_seconds("PT"));
+    assert_eq!(None, duration_to_seconds("PT10"));
+    assert_eq!(None, duration_to_seconds("PT1S1M"));
+    assert_eq!(None, duration_to_seconds("PT1M1M"));
+    assert_eq!(None, duration_to_seconds("3:10"));
+    assert_eq!(None, duration_to_seconds("PT99999999999999999999S"));
+    assert_eq!(None, duration_to_seconds("P99999999999999W"));
+}
+
+#[test]
+fn room_settings_patch() {
+    let mut settings = RoomSettings::default_for(1);
+
+    let valid = RoomSettingsPatch {
+        max_song_length: Some(Some(600)),
+        skip_policy: Some("disabled".to_string()),
+        ..Default::default()
+    };
+    assert!(valid.apply(&mut settings).is_ok());
+    assert_eq!(Some(600), settings.max_song_length);
+    assert_eq!(SkipPolicy::Disabled, settings.skip_policy);
+
+    let invalid = RoomSettingsPatch {
+        max_song_length: Some(Some(0)),
+        skip_policy: Some("anyone".to_string()),
+        fallback_mode: Some("shuffle".to_string()),
+        ..Default::default()
+    };
+    let errors = invalid.apply(&mut settings).unwrap_err();
+    let fields: Vec<&str> = errors.errors.iter().map(|e| &e.field[..]).collect();
+    assert_eq!(vec!["max_song_length", "fallback_mode"], fields);
+    // Nothing gets applied when a single field is invalid
+    assert_eq!(SkipPolicy::Disabled, settings.skip_policy);
+}
+
+#[test]
+fn room_patch_description() {
+    let missing: RoomPatch = serde_json::from_str(r#"{ "name": "metal" }"#).unwrap();
+    assert_eq!(Some("metal".to_string()), missing.name);
+    assert_eq!(None, missing.description);
+
+    let cleared: RoomPatch = serde_json::from_str(r#"{ "description": null }"#).unwrap();
+    assert_eq!(None, cleared.name);
+    assert_eq!(Some(None), cleared.description);
+}
+
+#[test]
+fn room_listing_query() {
+    let filter = RoomQuery::default().filter().unwrap();
+    assert_eq!((20, 0), (filter.limit, filter.offset));
+    assert_eq!((RoomSort::Name, false), (filter.sort, filter.descending));
+
+    let query = RoomQuery {
+        limit: Some("100".to_string()),
+        offset: Some("40".to_string()),
+        sort: Some("listeners".to_string()),
+        playing: Some("true".to_string()),
+        ..RoomQuery::default()
+    };
+    let filter = query.filter().unwrap();
+    assert_eq!((100, 40), (filter.limit, filter.offset));
+    assert_eq!((RoomSort::Listeners, true), (filter.sort, filter.descending));
+    assert_eq!(Some(true), filter.playing);
+
+    for &(limit, offset) in &[("0", "0"), ("101", "0"), ("abc", "0"), ("20", "-1"), ("20", "1.5")] {
+        let query = RoomQuery {
+            limit: Some(limit.to_string()),
+            offset: Some(offset.to_string()),
+            ..RoomQuery::default()
+        };
+        match query.filter() {
+            Err(ApiError::Validation(errors)) => assert_eq!(errors.errors.len(), 1),
+            _ => panic!("limit={} and offset={} were accepted", limit, offset),
+        }
+    }
+
+    let query = RoomQuery {
+        sort: Some("popular".to_string()),
+        has_picture: Some("yes".to_string()),
+        ..RoomQuery::default()
+    };
+    match query.filter() {
+        Err(ApiError::Validation(errors)) => {
+            let fields: Vec<&str> = errors.errors.iter().map(|e| &e.field[..]).collect();
+            assert_eq!(vec!["sort", "has_picture"], fields);
+        }
+        _ => panic!("An invalid sort was accepted"),
+    }
+}
+
+#[test]
+fn room_slugs() {
+    assert_eq!("death-metal", slugify("Death_Metal"));
+    assert_eq!("a-b", slugify("__a__b__"));
+    assert_eq!("room", slugify("___"));
+}
+
+#[test]
+fn name_rules() {
+    let rules = NameRules::default();
+
+    assert_eq!(Ok("Friday Night".to_string()), rules.check("  Ｆｒｉｄａｙ\u{a0} Night "));
+    assert_eq!(Ok("Metal 🤘".to_string()), rules.check("Metal 🤘"));
+    assert!(rules.check("ab").is_err());
+    assert!(rules.check("zero\u{200B}width").is_err());
+
+    let strict = NameRules {
+        charset: NameRules::parse_charset("letters, digits").unwrap(),
+        ..NameRules::default()
+    };
+    assert_eq!(
+        Err("Can't contain ' ', only letters and digits are allowed.".to_string()),
+        strict.check("Friday Night")
+    );
+    assert!(NameRules::parse_charset("letters,emoji").is_err());
+
+    assert_eq!(skeleton("Metal"), skeleton("METAL"));
+    assert_eq!(skeleton("Metal"), skeleton("M\u{0435}tal"));
+    assert_eq!(skeleton("room"), skeleton("r00m"));
+    assert_ne!(skeleton("metal"), skeleton("medal"));
+}
+
+#[test]
+fn picture_pipeline() {
+    let mut upload = Vec::new();
+    DynamicImage::new_rgb8(1024, 768)
+        .save(&mut upload, ImageFormat::JPEG)
+        .unwrap();
+
+    let processed = picture::process(&upload).unwrap();
+    let scaled = image::load_from_memory_with_format(&processed.picture, ImageFormat::PNG).unwrap();
+    assert_eq!((PICTURE_SIZE, 384), scaled.dimensions());
+
+    let sizes: Vec<u32> = processed.thumbnails.iter().map(|t| t.0).collect();
+    assert_eq!(THUMBNAIL_SIZES.to_vec(), sizes);
+
+    assert_eq!("42_64.png", picture::thumbnail_path("42.png", 64));
+    assert!(picture::process(b"not a picture").is_err());
+}
+
+#[test]
+fn picture_upload_limit() {
+    let upload = vec![0; MAX_UPLOAD_SIZE as usize];
+    assert_eq!(MAX_UPLOAD_SIZE as usize, picture::read_upload(&upload[..]).unwrap().len());
+
+    let upload = io::repeat(0);
+    assert!(picture::read_upload(upload).is_err());
+
+    // A BMP that claims to be 6000x6000 in its header, it's rejected without being decoded
+    let mut upload = Vec::new();
+    DynamicImage::new_rgb8(1, 1)
+        .save(&mut upload, ImageFormat::BMP)
+        .unwrap();
+    upload[18..22].copy_from_slice(&[0x70, 0x17, 0, 0]);
+    upload[22..26].copy_from_slice(&[0x70, 0x17, 0, 0]);
+
+    match picture::process(&upload) {
+        Err(Failure(status)) => assert_eq!(Status::UnprocessableEntity, status),
+        Ok(_) => panic!("A picture of {} pixels was decoded", 6000 * 6000),
+    }
+}
+
+#[test]
+fn local_picture_storage() {
+    let root = env::temp_dir().join(format!("youkebox-pictures-{}", std::process::id()));
+    let storage = LocalStorage::new(root.clone());
+
+    storage.put("42.png", b"old picture").unwrap();
+    storage.put("42.png", b"picture").unwrap();
+    assert_eq!(Some(b"picture".to_vec()), storage.get("42.png").unwrap());
+
+    // Nothing but the picture itself is left behind
+    assert_eq!(1, fs::read_dir(&root).unwrap().count());
+
+    storage.delete("42.png").unwrap();
+    assert_eq!(None, storage.get("42.png").unwrap());
+    assert!(storage.delete("42.png").is_ok());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+// A tiny stand-in for an S3 compatible service that keeps objects in memory
+// Reads a request to one of the stub servers: the method, the path, the headers with
+// lowercased names and the body
+fn read_stub_request(
+    reader: &mut BufReader<TcpStream>,
+) -> (String, String, HashMap<String, String>, Vec<u8>) {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let parts: Vec<String> = request_line.split(' ').map(String::from).collect();
+    let (method, path) = (parts[0].clone(), parts[1].clone());
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        let header = header.trim_right();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(colon) = header.find(':') {
+            headers.insert(
+                header[..colon].trim().to_lowercase(),
+                header[colon + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .map(|length| length.parse().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    (method, path, headers, body)
+}
+
+fn write_stub_response(stream: &mut TcpStream, status: &str, content_type: Option<&str>, body: &[u8]) {
+    let content_type = content_type
+        .map(|content_type| format!("Content-Type: {}\r\n", content_type))
+        .unwrap_or_default();
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+}
+
+fn start_s3_stub() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let authorizations = Arc::new(Mutex::new(Vec::new()));
+    let seen = authorizations.clone();
+
+    thread::spawn(move || {
+        let mut objects: HashMap<String, Vec<u8>> = HashMap::new();
+
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let (method, path, headers, body) = read_stub_request(&mut reader);
+
+            if let Some(authorization) = headers.get("authorization") {
+                seen.lock().unwrap().push(authorization.clone());
+            }
+
+            let (status, body) = match &method[..] {
+                "PUT" => {
+                    objects.insert(path, body);
+                    ("200 OK", Vec::new())
+                }
+                "GET" => match objects.get(&path) {
+                    Some(object) => ("200 OK", object.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                },
+                _ => {
+                    objects.remove(&path);
+                    ("204 No Content", Vec::new())
+                }
+            };
+
+            write_stub_response(&mut stream, status, None, &body);
+        }
+    });
+
+    (endpoint, authorizations)
+}
+
+#[test]
+fn s3_picture_storage() {
+    let (endpoint, authorizations) = start_s3_stub();
+
+    let storage = S3Storage {
+        endpoint,
+        bucket: "pictures".to_string(),
+        region: "us-east-1".to_string(),
+        access_key: "minio".to_string(),
+        secret_key: "minio123".to_string(),
+    };
+
+    storage.put("42.png", b"picture").unwrap();
+    assert_eq!(Some(b"picture".to_vec()), storage.get("42.png").unwrap());
+
+    storage.delete("42.png").unwrap();
+    assert_eq!(None, storage.get("42.png").unwrap());
+
+    let authorizations = authorizations.lock().unwrap();
+    assert_eq!(4, authorizations.len());
+    assert!(authorizations.iter().all(|authorization| {
+        authorization.starts_with("AWS4-HMAC-SHA256 Credential=minio/")
+            && authorization.contains("/us-east-1/s3/aws4_request")
+            && authorization.contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date")
+    }));
+}
+
+#[test]
+fn api_errors() {
+    let error = ApiError::from(Failure(Status::NotFound));
+    assert_eq!(Status::NotFound, error.status());
+    assert_eq!("not_found", error.code());
+
+    let error = ApiError::from(Status::UnsupportedMediaType);
+    assert_eq!(415, error.status().code);
+    assert_eq!("unsupported_media_type", error.code());
+    assert_eq!("Unsupported Media Type", error.message());
+
+    let mut errors = ValidationErrors::new();
+    errors.add("name", "Is already taken.");
+    let error = ApiError::from(errors.with_status(Status::Conflict));
+    assert_eq!(Status::Conflict, error.status());
+    assert_eq!("validation_failed", error.code());
+
+    let body = serde_json::to_value(HttpStatus::new(Status::TooManyRequests, "Slow down")).unwrap();
+    assert_eq!(
+        json!({ "status": 429, "code": "too_many_requests", "message": "Slow down" }),
+        body
+    );
+}
+
+#[test]
+fn log_format() {
+    assert_eq!(Some(LogFormat::Json), LogFormat::parse("json"));
+    assert_eq!(Some(LogFormat::Text), LogFormat::parse("text"));
+    assert_eq!(None, LogFormat::parse("xml"));
+}
+
+#[test]
+fn redacted_invite_tokens() {
+    assert_eq!("/api/v1/rooms/4", redact_uri("/api/v1/rooms/4"));
+    assert_eq!(
+        "/api/v1/rooms/4/stats?days=7&invite=[redacted]&limit=5",
+        redact_uri("/api/v1/rooms/4/stats?days=7&invite=s3cr3t&limit=5")
+    );
+    assert_eq!("/api/v1/rooms/4?invite=[redacted]", redact_uri("/api/v1/rooms/4?invite"));
+    assert_eq!("/api/v1/rooms?inviter=bob", redact_uri("/api/v1/rooms?inviter=bob"));
+}
+
+#[test]
+fn prometheus_metrics() {
+    let mut metrics = Metrics::default();
+    metrics.observe_request("GET", "/api/v1/rooms/<id>", 200, Duration::from_millis(20));
+    metrics.observe_request("GET", "/api/v1/rooms/<id>", 200, Duration::from_millis(200));
+    metrics.youtube_call(YoutubeCall::Search, true);
+    metrics.youtube_call(YoutubeCall::Search, false);
+    metrics.video_skipped(7);
+    metrics.picture_uploaded(1000);
+
+    let mut out = String::new();
+    metrics.render(&mut out);
+    let lines: Vec<&str> = out.lines().collect();
+
+    assert!(lines.contains(&r#"youkebox_http_requests_total{method="GET",route="/api/v1/rooms/<id>",status="200"} 2"#));
+    assert!(lines.contains(&r#"youkebox_http_request_duration_seconds_bucket{method="GET",route="/api/v1/rooms/<id>",le="0.025"} 1"#));
+    assert!(lines.contains(&r#"youkebox_http_request_duration_seconds_bucket{method="GET",route="/api/v1/rooms/<id>",le="+Inf"} 2"#));
+    assert!(lines.contains(&r#"youkebox_youtube_failures_total{call="search"} 1"#));
+    assert!(lines.contains(&r#"youkebox_youtube_quota_units_total{call="search"} 200"#));
+    assert!(lines.contains(&r#"youkebox_videos_skipped_total{room_id="7"} 1"#));
+    assert!(lines.contains(&"youkebox_picture_upload_bytes_count 1"));
+}
+
+#[test]
+fn readiness_checks() {
+    let mut checks = BTreeMap::new();
+    checks.insert("database", Check::ok());
+    checks.insert("youtube_api_key", Check::ok());
+
+    let readiness = Readiness::new(checks.clone());
+    assert!(readiness.is_ready());
+    assert_eq!(
+        serde_json::to_value(&readiness).unwrap(),
+        json!({ "status": "ok", "checks": { "database": { "status": "ok" }, "youtube_api_key": { "status": "ok" } } })
+    );
+
+    checks.insert("migrations", Check::fail("2 migrations are pending"));
+
+    let readiness = Readiness::new(checks);
+    assert!(!readiness.is_ready());
+    assert_eq!(
+        serde_json::to_value(&readiness).unwrap()["checks"]["migrations"],
+        json!({ "status": "fail", "message": "2 migrations are pending" })
+    );
+}
+
+#[test]
+fn player_readiness() {
+    let synced = Some(Duration::from_secs(10));
+    assert!(check_player(true, synced, None).is_ok());
+    assert!(check_player(true, synced, Some(Duration::from_secs(3600))).is_ok());
+
+    assert!(!check_player(false, synced, None).is_ok());
+    assert!(!check_player(true, None, None).is_ok());
+    assert_eq!(
+        Check::fail("The players weren't synced for 600 seconds"),
+        check_player(true, Some(Duration::from_secs(600)), None)
+    );
+    assert_eq!(
+        Check::fail("A player thread panicked 5 seconds ago"),
+        check_player(true, synced, Some(Duration::from_secs(5)))
+    );
+}
+
+#[test]
+fn embedded_migrations() {
+    let mut directories: Vec<String> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    directories.sort();
+
+    let embedded: Vec<String> = MIGRATIONS
+        .iter()
+        .map(|migration| format!("{}_{}", migration.version, migration.name))
+        .collect();
+
+    assert_eq!(embedded, directories);
+}
+
+#[test]
+fn room_import_versions() {
+    let export = json!({
+        "version": EXPORT_VERSION,
+        "room": { "name": "metal", "description": null, "is_public": true, "owner": null },
+        "settings": { "max_song_length": 600, "skip_policy": "disabled" },
+        "queue": [
+            { "video_id": "ZnJVcuUDnW4", "title": "Song", "description": null, "duration": "PT3M10S",
+              "added_on": { "secs_since_epoch": 1500000000, "nanos_since_epoch": 0 }, "started_on": null }
+        ]
+    });
+
+    let import = RoomImport::parse(&export.to_string()).unwrap();
+    assert_eq!(import.room.name, "metal");
+    assert_eq!(import.settings.max_song_length, Some(Some(600)));
+    assert_eq!(import.queue.len(), 1);
+    assert!(import.history.is_empty());
+    assert!(import.validate().is_ok());
+
+    let mut tampered = export.clone();
+    tampered["settings"]["skip_policy"] = json!("owner_only");
+    tampered["queue"][0]["duration"] = json!("PT99999999999999999999S");
+    tampered["history"] = json!([
+        { "video_id": "<script>", "title": " ", "description": null, "duration": "PT1M",
+          "added_on": { "secs_since_epoch": 1500000000, "nanos_since_epoch": 0 }, "started_on": null }
+    ]);
+
+    match RoomImport::parse(&tampered.to_string()).unwrap().validate() {
+        Err(ApiError::Validation(errors)) => {
+            let fields: Vec<&str> = errors.errors.iter().map(|e| &e.field[..]).collect();
+            assert_eq!(
+                fields,
+                vec!["settings.skip_policy", "queue[0].duration", "history[0].video_id", "history[0].title"]
+            );
+        }
+        _ => panic!("An export with invalid settings and songs was accepted"),
+    }
+
+    let mut newer = export.clone();
+    newer["version"] = json!(EXPORT_VERSION + 1);
+
+    match RoomImport::parse(&newer.to_string()) {
+        Err(ApiError::Validation(errors)) => assert_eq!(errors.errors[0].field, "version"),
+        _ => panic!("An export of an unknown version was accepted"),
+    }
+
+    let mut unversioned = export.clone();
+    unversioned.as_object_mut().unwrap().remove("version");
+    assert!(RoomImport::parse(&unversioned.to_string()).is_err());
+
+    match RoomImport::parse("{ \"version\": 1, \"room\": 42 }") {
+        Err(ApiError::Validation(errors)) => assert_eq!(errors.errors[0].field, "body"),
+        _ => panic!("A broken export was accepted"),
+    }
+
+    let oversized = io::repeat(b' ').take(MAX_IMPORT_SIZE + 1);
+    match RoomImport::read(oversized) {
+        Err(error) => assert_eq!(error.status(), Status::PayloadTooLarge),
+        _ => panic!("An export larger than MAX_IMPORT_SIZE was accepted"),
+    }
+}
+
+#[test]
+fn playlist_files() {
+    let entries = vec![
+        PlaylistEntry {
+            url: "https://www.youtube.com/watch?v=ZnJVcuUDnW4".to_string(),
+            title: "Slayer, \"Raining Blood\" <live>".to_string(),
+            duration: 250,
+        },
+    ];
+
+    assert_eq!(
+        PlaylistFormat::M3u.render("metal", &entries),
+        "#EXTM3U\n#EXTINF:250,Slayer, \"Raining Blood\" <live>\nhttps://www.youtube.com/watch?v=ZnJVcuUDnW4\n"
+    );
+    assert_eq!(
+        PlaylistFormat::Csv.render("metal", &entries),
+        "url,title,duration\r\nhttps://www.youtube.com/watch?v=ZnJVcuUDnW4,\"Slayer, \"\"Raining Blood\"\" <live>\",250\r\n"
+    );
+
+    let xspf = PlaylistFormat::Xspf.render("metal & more", &entries);
+    assert!(xspf.contains("<title>metal &amp; more</title>"));
+    assert!(xspf.contains("<title>Slayer, &quot;Raining Blood&quot; &lt;live&gt;</title>"));
+    assert!(xspf.contains("<duration>250000</duration>"));
+
+    let range = PlaylistQuery::default().range().unwrap();
+    assert_eq!(range.list, PlaylistList::History);
+    assert_eq!(range.from, None);
+
+    let query = PlaylistQuery {
+        list: Some("queue".to_string()),
+        from: Some("2017-07-14".to_string()),
+        to: Some("2017-07-14".to_string()),
+    };
+    let range = query.range().unwrap();
+    assert_eq!(range.list, PlaylistList::Queue);
+    assert_eq!(range.from, Some(UNIX_EPOCH + Duration::from_secs(1499990400)));
+    assert_eq!(range.to, Some(UNIX_EPOCH + Duration::from_secs(1499990400 + 24 * 60 * 60)));
+
+    let query = PlaylistQuery {
+        list: None,
+        from: Some("2017-07-14T02:40:00Z".to_string()),
+        to: Some("2017-07-14T02:40:00+01:00".to_string()),
+    };
+    match query.range() {
+        Err(ApiError::Validation(errors)) => assert_eq!(errors.errors[0].field, "to"),
+        _ => panic!("A range that ends before it starts was accepted"),
+    }
+
+    let query = PlaylistQuery {
+        list: Some("played".to_string()),
+        from: Some("last week".to_string()),
+        to: None,
+    };
+    match query.range() {
+        Err(ApiError::Validation(errors)) => assert_eq!(errors.errors.len(), 2),
+        _ => panic!("An invalid list and date were accepted"),
+    }
+}
+
+#[test]
+fn saved_playlist_names() {
+    assert_eq!(saved_playlist::check_name("  Chill  ").unwrap(), "Chill");
+
+    match saved_playlist::check_name(" \t ") {
+        Err(ApiError::Validation(errors)) => assert_eq!(errors.errors[0].field, "name"),
+        _ => panic!("An empty playlist name was accepted"),
+    }
+
+    let longest: String = "é".repeat(MAX_PLAYLIST_NAME_LENGTH);
+    assert!(saved_playlist::check_name(&longest).is_ok());
+    assert!(saved_playlist::check_name(&format!("{}e", longest)).is_err());
+}
+
+// A stand-in for the playlist endpoints of the YouTube Data API, it records every request
+// and only accepts the access token "valid"
+fn start_youtube_stub() -> (String, Arc<Mutex<Vec<(String, String, serde_json::Value)>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_url = format!("http://{}/youtube/v3", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+
+    thread::spawn(move || {
+        for (count, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let (_, path, headers, body) = read_stub_request(&mut reader);
+            let authorization = headers.get("authorization").cloned().unwrap_or_default();
+
+            let (status, response) = if authorization != "Bearer valid" {
+                ("401 Unauthorized", json!({ "error": { "code": 401 } }))
+            } else if path.starts_with("/youtube/v3/playlists?") {
+                ("200 OK", json!({ "id": "PLfavourites" }))
+            } else {
+                ("200 OK", json!({ "id": format!("item{}", count) }))
+            };
+
+            seen.lock()
+                .unwrap()
+                .push((path, authorization, serde_json::from_slice(&body).unwrap()));
+
+            let response = response.to_string();
+            write_stub_response(&mut stream, status, Some("application/json"), response.as_bytes());
+        }
+    });
+
+    (api_url, requests)
+}
+
+fn favourite(video_id: &str, added: u64) -> Favourite {
+    Favourite {
+        user_id: 1,
+        video_id: video_id.to_string(),
+        title: video_id.to_uppercase(),
+        description: None,
+        duration: "PT3M".to_string(),
+        room_id: Some(1),
+        added_on: UNIX_EPOCH + Duration::from_secs(added),
+    }
+}
+
+#[test]
+fn youtube_playlist_export() {
+    let (api_url, requests) = start_youtube_stub();
+    let youtube = YoutubePlaylists { api_url };
+
+    // Newest first, like Favourite::list returns them
+    let favourites = vec![favourite("second", 2000), favourite("first", 1000)];
+
+    let exported = youtube.export("valid", "Metal", &favourites).unwrap();
+    assert_eq!(
+        ExportedPlaylist {
+            playlist_id: "PLfavourites".to_string(),
+            url: "https://www.youtube.com/playlist?list=PLfavourites".to_string(),
+            videos: 2,
+        },
+        exported
+    );
+
+    match youtube.export("expired", "Metal", &favourites) {
+        Err(ApiError::Unauthorized) => {}
+        _ => panic!("An expired access token wasn't reported"),
+    }
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(4, requests.len());
+
+    let (ref path, ref authorization, ref body) = requests[0];
+    assert_eq!("/youtube/v3/playlists?part=snippet,status", path);
+    assert_eq!("Bearer valid", authorization);
+    assert_eq!("Metal", body["snippet"]["title"]);
+    assert_eq!("private", body["status"]["privacyStatus"]);
+
+    // The oldest favourite goes first
+    let added: Vec<&str> = requests[1..3]
+        .iter()
+        .map(|&(ref path, _, ref body)| {
+            assert!(path.starts_with("/youtube/v3/playlistItems?"));
+            assert_eq!("PLfavourites", body["snippet"]["playlistId"]);
+            body["snippet"]["resourceId"]["videoId"].as_str().unwrap()
+        })
+        .collect();
+    assert_eq!(vec!["first", "second"], added);
+}
+
+#[test]
+fn room_stats_listening() {
+    let at = |seconds: u64| UNIX_EPOCH + Duration::from_secs(seconds);
+    let hour = 60 * 60;
+
+    let plays = vec![
+        Play { started_on: at(9 * hour), skipped_on: None, duration: 200 },
+        Play { started_on: at(9 * hour + 200), skipped_on: Some(at(9 * hour + 230)), duration: 300 },
+        Play { started_on: at(14 * hour), skipped_on: None, duration: 100 },
+        // Skipped after the song should have ended, only the song itself counts
+        Play { started_on: at(24 * hour + 9 * hour), skipped_on: Some(at(34 * hour)), duration: 60 },
+    ];
+
+    let listening = Listening::summarize(&plays, 10);
+    assert_eq!(4, listening.songs_played);
+    assert_eq!(2, listening.songs_skipped);
+    assert_eq!(0.5, listening.skip_rate);
+    assert_eq!(200 + 30 + 100 + 60, listening.listening_time);
+    assert_eq!(
+        vec![HourStats { hour: 9, plays: 3 }, HourStats { hour: 14, plays: 1 }],
+        listening.busiest_hours
+    );
+
+    assert_eq!(1, Listening::summarize(&plays, 1).busiest_hours.len());
+    assert_eq!(0.0, Listening::summarize(&[], 10).skip_rate);
+
+    assert_eq!((7, 10), StatsQuery::default().parse().unwrap());
+
+    let query = StatsQuery {
+        days: Some("400".to_string()),
+        limit: Some("ten".to_string()),
+    };
+    match query.parse() {
+        Err(ApiError::Validation(errors)) => {
+            let fields: Vec<&str> = errors.errors.iter().map(|e| &e.field[..]).collect();
+            assert_eq!(vec!["days", "limit"], fields);
+        }
+        _ => panic!("An invalid stats window was accepted"),
+    }
+}
+
+#[test]
+fn trending_config() {
+    let config = Config::build(Environment::Development).finalize().unwrap();
+    assert_eq!(TrendingConfig::default(), TrendingConfig::from_config(&config));
+
+    let config = Config::build(Environment::Development)
+        .extra("TRENDING_WINDOW_HOURS", 168)
+        .extra("TRENDING_INTERVAL_MINUTES", 0)
+        .finalize()
+        .unwrap();
+    let trending = TrendingConfig::from_config(&config);
+    assert_eq!(168, trending.window_hours);
+    assert_eq!(Duration::from_secs(7 * 24 * 60 * 60), trending.window());
+    // The job never runs back to back
+    assert_eq!(1, trending.interval_minutes);
+}
+
+#[test]
+fn trending_query() {
+    assert_eq!(10, TrendingQuery::default().parse().unwrap());
+    assert_eq!(50, TrendingQuery { limit: Some("50".to_string()) }.parse().unwrap());
+
+    for limit in &["0", "51", "abc", ""] {
+        match (TrendingQuery { limit: Some(limit.to_string()) }).parse() {
+            Err(ApiError::Validation(errors)) => assert_eq!(errors.errors[0].field, "limit"),
+            _ => panic!("limit={} was accepted", limit),
+        }
+    }
+}
+
+#[test]
+fn anonymous_listener_ids() {
+    let config = ListenerConfig::new("secret", None);
+    let id = config.issue(4, "10.0.0.1");
+
+    assert!(config.verify(&id, 4, "10.0.0.1"));
+    // Ids only work in the room and from the address they were handed out to
+    assert!(!config.verify(&id, 5, "10.0.0.1"));
+    assert!(!config.verify(&id, 4, "10.0.0.2"));
+    assert!(!ListenerConfig::new("other secret", None).verify(&id, 4, "10.0.0.1"));
+
+    // Every heartbeat without an id gets a new one
+    assert_ne!(id, config.issue(4, "10.0.0.1"));
+
+    let mut tampered = id.clone();
+    tampered.pop();
+    tampered.push(if id.ends_with('0') { '1' } else { '0' });
+    assert!(!config.verify(&tampered, 4, "10.0.0.1"));
+    assert!(!config.verify("user-4", 4, "10.0.0.1"));
+    assert!(!config.verify("", 4, "10.0.0.1"));
 }